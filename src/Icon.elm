@@ -22,10 +22,13 @@ module Icon exposing
     , play
     , point
     , polygon
+    , rotateCcw
+    , rotateCw
     , settings
     , toHtml
     , trash2
     , until
+    , upload
     , zoomFit
     , zoomIn
     , zoomOut
@@ -192,6 +195,14 @@ download =
     ]
 
 
+upload : List (Svg msg)
+upload =
+    [ Svg.path [ d "M21 15v4a2 2 0 0 1-2 2H5a2 2 0 0 1-2-2v-4" ] []
+    , Svg.polyline [ points "17 8 12 3 7 8" ] []
+    , Svg.line [ x1 "12", y1 "3", x2 "12", y2 "15" ] []
+    ]
+
+
 image : List (Svg msg)
 image =
     [ Svg.rect [ x "3", y "3", width "18", height "18", rx "2", ry "2" ] []
@@ -200,6 +211,20 @@ image =
     ]
 
 
+rotateCcw : List (Svg msg)
+rotateCcw =
+    [ Svg.polyline [ points "1 4 1 10 7 10" ] []
+    , Svg.path [ d "M3.51 15a9 9 0 1 0 2.13-9.36L1 10" ] []
+    ]
+
+
+rotateCw : List (Svg msg)
+rotateCw =
+    [ Svg.polyline [ points "23 4 23 10 17 10" ] []
+    , Svg.path [ d "M20.49 15a9 9 0 1 1-2.12-9.36L23 10" ] []
+    ]
+
+
 chevronLeft : List (Svg msg)
 chevronLeft =
     [ Svg.polyline [ points "15 18 9 12 15 6" ] [] ]
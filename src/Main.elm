@@ -24,7 +24,7 @@ import Svg.Attributes as SvgA
 main : Program Device.Size State Msg
 main =
     Browser.element
-        { init = \size -> ( Initial (Device.classify size) (Camera "icl"), Cmd.none )
+        { init = \size -> ( Initial (Device.classify size) (Camera "icl") Nothing Nothing, Cmd.none )
         , view = view
         , update = update
         , subscriptions = subscriptions
@@ -32,7 +32,7 @@ main =
 
 
 type State
-    = Initial Device Camera
+    = Initial Device Camera (Maybe Value) (Maybe LoadingError)
     | DatasetLoaded Device Int Slider Bool Fps Fixer
 
 
@@ -42,6 +42,7 @@ type Fixer
     | KeyframesPair Int Int
     | InteractiveFix Int Int (List PointFix) (List PointFix)
     | InitializationSelector Int (List Float)
+    | ExportMenu
 
 
 type alias PointFix =
@@ -55,6 +56,14 @@ type Camera
     = Camera String
 
 
+{-| Error of the last attempt to load a dataset, as thrown by wasm.
+-}
+type alias LoadingError =
+    { code : String
+    , message : String
+    }
+
+
 type alias Fps =
     { exact : Float
     , smoothed : Float
@@ -70,20 +79,14 @@ type alias Slider =
     }
 
 
-initialSlider : Slider
-initialSlider =
-    { min = 0
-    , max = 0
-    , current = 0
-    }
-
-
 type Msg
     = Track Float
     | Pick Float
     | SelectCamera Camera
+    | SelectSession Value
     | LoadDataset Value
-    | DatasetLoadedMsg Int
+    | DatasetLoadedMsg { nbFrames : Int, lastKeyframe : Int }
+    | LoadingFailed LoadingError
     | WindowResizes Device.Size
     | NewKeyFrame Int
     | ToogleTracking
@@ -94,20 +97,33 @@ type Msg
     | ClickKey ( Float, Float )
     | P3pProbabilities (List Float)
     | ChooseInitial Int
-    | ExportObj
+    | ToggleExportMenu
+    | Export String
+    | ToggleReconstruction
+    | Undo
+    | Redo
+    | HistoryChanged Int
 
 
 update : Msg -> State -> ( State, Cmd Msg )
 update msg model =
     case ( msg, model ) of
-        ( SelectCamera camera, Initial device _ ) ->
-            ( Initial device camera, Cmd.none )
+        ( SelectCamera camera, Initial device _ session error ) ->
+            ( Initial device camera session error, Cmd.none )
+
+        ( SelectSession session, Initial device camera _ error ) ->
+            ( Initial device camera (Just session) error, Cmd.none )
+
+        ( LoadDataset jsValue, Initial device (Camera camera) session _ ) ->
+            ( Initial device (Camera camera) session Nothing
+            , Ports.loadDataset { file = jsValue, camera = camera, session = session }
+            )
 
-        ( LoadDataset jsValue, Initial _ (Camera camera) ) ->
-            ( model, Ports.loadDataset { file = jsValue, camera = camera } )
+        ( DatasetLoadedMsg { nbFrames, lastKeyframe }, Initial device _ _ _ ) ->
+            ( DatasetLoaded device nbFrames (sliderRestart lastKeyframe) False (Fps 60 60 60 0) NoFix, Cmd.none )
 
-        ( DatasetLoadedMsg nb_frames, Initial device _ ) ->
-            ( DatasetLoaded device nb_frames initialSlider False (Fps 60 60 60 0) NoFix, Cmd.none )
+        ( LoadingFailed error, Initial device camera session _ ) ->
+            ( Initial device camera session (Just error), Cmd.none )
 
         ( Track delta, DatasetLoaded device nb_frames slid play fps fixer ) ->
             let
@@ -219,12 +235,30 @@ update msg model =
             , Ports.chooseP3pInitial { id = id, base_kf = baseKf }
             )
 
-        ( ExportObj, DatasetLoaded _ _ _ _ _ _ ) ->
-            ( model, Ports.exportObj () )
+        ( ToggleExportMenu, DatasetLoaded device nb_frames slid play fps NoFix ) ->
+            ( DatasetLoaded device nb_frames slid play fps ExportMenu, Cmd.none )
+
+        ( ToggleExportMenu, DatasetLoaded device nb_frames slid play fps ExportMenu ) ->
+            ( DatasetLoaded device nb_frames slid play fps NoFix, Cmd.none )
+
+        ( Export artifact, DatasetLoaded device nb_frames slid play fps ExportMenu ) ->
+            ( DatasetLoaded device nb_frames slid play fps NoFix, Ports.exportArtifact artifact )
+
+        ( ToggleReconstruction, DatasetLoaded device nb_frames slid play fps ExportMenu ) ->
+            ( DatasetLoaded device nb_frames slid play fps NoFix, Ports.toggleReconstruction () )
+
+        ( Undo, DatasetLoaded _ _ _ False _ _ ) ->
+            ( model, Ports.undo () )
+
+        ( Redo, DatasetLoaded _ _ _ False _ _ ) ->
+            ( model, Ports.redo () )
+
+        ( HistoryChanged lastKeyframe, DatasetLoaded device nb_frames _ play fps _ ) ->
+            ( DatasetLoaded device nb_frames (sliderRestart lastKeyframe) play fps NoFix, Cmd.none )
 
         -- Window resizes
-        ( WindowResizes size, Initial device camera ) ->
-            ( Initial { device | size = size } camera, Cmd.none )
+        ( WindowResizes size, Initial device camera session error ) ->
+            ( Initial { device | size = size } camera session error, Cmd.none )
 
         ( WindowResizes size, DatasetLoaded device nb_frames slid play fps fixer ) ->
             ( DatasetLoaded { device | size = size } nb_frames slid play fps fixer, Cmd.none )
@@ -344,10 +378,11 @@ updateFps delta fps =
 subscriptions : State -> Sub Msg
 subscriptions state =
     case state of
-        Initial _ _ ->
+        Initial _ _ _ _ ->
             Sub.batch
                 [ Ports.resizes WindowResizes
                 , Ports.datasetLoaded DatasetLoadedMsg
+                , Ports.loadingFailed LoadingFailed
                 ]
 
         DatasetLoaded _ _ _ _ _ _ ->
@@ -356,6 +391,7 @@ subscriptions state =
                 , Ports.animationFrame Track
                 , Ports.newKeyFrame NewKeyFrame
                 , Ports.p3pProbabilities P3pProbabilities
+                , Ports.historyChanged HistoryChanged
                 ]
 
 
@@ -367,8 +403,8 @@ view model =
 appLayout : State -> Element Msg
 appLayout model =
     case model of
-        Initial _ camera ->
-            initialLayout camera
+        Initial _ camera session error ->
+            initialLayout camera session error
 
         DatasetLoaded device nb_frames slid play fps fixer ->
             let
@@ -383,10 +419,11 @@ appLayout model =
                 ]
 
 
-initialLayout : Camera -> Element Msg
-initialLayout camera =
+initialLayout : Camera -> Maybe Value -> Maybe LoadingError -> Element Msg
+initialLayout camera session error =
     Element.column [ centerX, centerY, Element.spacing 30 ]
-        [ Input.radio []
+        [ loadingErrorView error
+        , Input.radio []
             { onChange = SelectCamera
             , selected = Just camera
             , label = Input.labelAbove [] (Element.text "Camera model:")
@@ -397,10 +434,22 @@ initialLayout camera =
                 , Input.option (Camera "fr3") (Element.text "TUM Freiburg 3")
                 ]
             }
+        , Element.column [] [ Element.text "Session to restore (optional):", loadSessionButton session ]
         , Element.column [] [ Element.text "Dataset to load:", loadDatasetButton LoadDataset ]
         ]
 
 
+loadingErrorView : Maybe LoadingError -> Element msg
+loadingErrorView maybeError =
+    case maybeError of
+        Nothing ->
+            Element.none
+
+        Just { message } ->
+            Element.paragraph [ Element.Font.color (rgb255 200 0 0), width (px 400) ]
+                [ Element.text ("Loading failed, " ++ message) ]
+
+
 renderer : Device.Size -> Int -> Slider -> Fixer -> Element Msg
 renderer size nb_frames s fixer =
     let
@@ -430,6 +479,11 @@ renderer size nb_frames s fixer =
                     ( interactiveKeyframeCanvas "none" []
                     , interactiveReferenceCanvas "none" "none" []
                     )
+
+                ExportMenu ->
+                    ( interactiveKeyframeCanvas "none" []
+                    , interactiveReferenceCanvas "none" "none" []
+                    )
     in
     Element.Keyed.el
         [ width fill
@@ -517,7 +571,15 @@ keyframeCanvas =
 
 htmlKeyframeCanvas : String -> String -> Html msg
 htmlKeyframeCanvas id display =
-    Html.canvas [ Attr.id id, Attr.width 320, Attr.height 240, Attr.style "display" display ] []
+    Html.canvas
+        [ Attr.id id
+        , Attr.width 320
+        , Attr.height 240
+        , Attr.style "width" "320px"
+        , Attr.style "height" "240px"
+        , Attr.style "display" display
+        ]
+        []
 
 
 customRenderer : Device.Size -> Int -> Slider -> Html msg
@@ -548,7 +610,9 @@ bottomToolbar slid play fps fixer =
         , pickRefButton play slid.current
         , restartFromButton play slid.current fixer
         , interactiveFixButton fixer
-        , exportObjButton
+        , undoButton play
+        , redoButton play
+        , exportButton fixer
         ]
 
 
@@ -561,6 +625,9 @@ fixerHelper fixer =
         InitializationSelector _ probabilities ->
             initializationSelectorView probabilities
 
+        ExportMenu ->
+            exportMenuView
+
         _ ->
             Element.none
 
@@ -579,6 +646,33 @@ initializationSelectorView probabilities =
         (List.indexedMap probaToChoice probabilities)
 
 
+exportMenuView : Element Msg
+exportMenuView =
+    Element.column
+        [ Element.alignRight
+        , Element.padding 10
+        , Element.spacing 10
+        , Element.clip
+        , Element.Font.size 20
+        , Element.Font.color (Element.rgb 1 1 1)
+        , Background.color (Element.rgba255 0 0 0 0.8)
+        ]
+        [ exportChoice "point_cloud" "Point cloud (ply)"
+        , exportChoice "scene" "Point cloud and cameras (obj)"
+        , exportChoice "mesh" "Mesh (ply)"
+        , el [ Element.pointer, Element.Events.onClick ToggleReconstruction ]
+            (Element.text "Toggle live reconstruction")
+        , exportChoice "trajectory" "Trajectories (txt)"
+        , exportChoice "session" "Session"
+        , exportChoice "edit_log" "Edit log (json)"
+        ]
+
+
+exportChoice : String -> String -> Element Msg
+exportChoice artifact text =
+    el [ Element.pointer, Element.Events.onClick (Export artifact) ] (Element.text text)
+
+
 probaToChoice : Int -> Float -> Element Msg
 probaToChoice id proba =
     coloredChoice (viridisLight id)
@@ -628,9 +722,35 @@ playPauseButton play =
     abledButton ToogleTracking title (Icon.toHtml 30 icon)
 
 
-exportObjButton : Element Msg
-exportObjButton =
-    abledButton ExportObj "Export to obj file" (Icon.toHtml 30 Icon.download)
+undoButton : Bool -> Element Msg
+undoButton play =
+    if play then
+        disabledButton "Undo last correction" (Icon.toHtml 30 Icon.rotateCcw)
+
+    else
+        abledButton Undo "Undo last correction" (Icon.toHtml 30 Icon.rotateCcw)
+
+
+redoButton : Bool -> Element Msg
+redoButton play =
+    if play then
+        disabledButton "Redo correction" (Icon.toHtml 30 Icon.rotateCw)
+
+    else
+        abledButton Redo "Redo correction" (Icon.toHtml 30 Icon.rotateCw)
+
+
+exportButton : Fixer -> Element Msg
+exportButton fixer =
+    case fixer of
+        NoFix ->
+            abledButton ToggleExportMenu "Export" (Icon.toHtml 30 Icon.download)
+
+        ExportMenu ->
+            activeButton ToggleExportMenu "Export" (Icon.toHtml 30 Icon.download)
+
+        _ ->
+            disabledButton "Export" (Icon.toHtml 30 Icon.download)
 
 
 pickRefButton : Bool -> Int -> Element Msg
@@ -694,6 +814,9 @@ interactiveFixButton fixer =
         InitializationSelector _ _ ->
             disabledButton "Pick points to fix camera pose" (Icon.toHtml 30 Icon.edit)
 
+        ExportMenu ->
+            disabledButton "Pick points to fix camera pose" (Icon.toHtml 30 Icon.edit)
+
 
 activeButton : msg -> String -> Html msg -> Element msg
 activeButton msg title icon =
@@ -748,6 +871,9 @@ slider fixer s =
 
                 InitializationSelector _ _ ->
                     Element.none
+
+                ExportMenu ->
+                    Element.none
     in
     Input.slider
         [ height fill
@@ -810,6 +936,40 @@ loadDatasetButton loadDatasetMsg =
     Element.row [] [ icon, Element.html invisibleInput ]
 
 
+loadSessionButton : Maybe Value -> Element Msg
+loadSessionButton session =
+    let
+        uniqueId =
+            "load-session"
+
+        ( background, title ) =
+            case session of
+                Nothing ->
+                    ( [ Element.mouseOver [ Background.color Style.hoveredItemBG ] ]
+                    , "Load session file, before the dataset it was saved on"
+                    )
+
+                Just _ ->
+                    ( [ Background.color Style.hoveredItemBG ]
+                    , "Session selected, restored when the dataset is loaded"
+                    )
+
+        icon =
+            [ Icon.toHtml 60 Icon.upload ]
+                |> Html.label (iconLabelAttributes uniqueId)
+                |> Element.html
+                |> Element.el (Element.htmlAttribute (Attr.title title) :: background)
+
+        invisibleInput =
+            FileInput.invisible
+                { id = uniqueId
+                , accept = ".vors,.json"
+                , quantity = FileInput.SingleWith SelectSession
+                }
+    in
+    Element.row [] [ icon, Element.html invisibleInput ]
+
+
 iconLabelAttributes : String -> List (Html.Attribute msg)
 iconLabelAttributes uniqueId =
     -- need to manually add a cursor because the class given by elm-ui
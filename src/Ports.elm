@@ -7,15 +7,20 @@ port module Ports exposing
     ( animationFrame
     , chooseP3pInitial
     , datasetLoaded
-    , exportObj
+    , exportArtifact
+    , historyChanged
     , loadDataset
+    , loadingFailed
     , newKeyFrame
     , p3pProbabilities
     , p3pVisualize
     , pickReference
+    , redo
     , resizes
     , restartFrom
+    , toggleReconstruction
     , track
+    , undo
     )
 
 import Json.Encode exposing (Value)
@@ -25,10 +30,13 @@ import Packages.Device as Device
 port resizes : (Device.Size -> msg) -> Sub msg
 
 
-port loadDataset : { file : Value, camera : String } -> Cmd msg
+port loadDataset : { file : Value, camera : String, session : Maybe Value } -> Cmd msg
 
 
-port datasetLoaded : (Int -> msg) -> Sub msg
+port datasetLoaded : ({ nbFrames : Int, lastKeyframe : Int } -> msg) -> Sub msg
+
+
+port loadingFailed : ({ code : String, message : String } -> msg) -> Sub msg
 
 
 port animationFrame : (Float -> msg) -> Sub msg
@@ -59,4 +67,16 @@ port p3pProbabilities : (List Float -> msg) -> Sub msg
 port chooseP3pInitial : { id : Int, base_kf : Int } -> Cmd msg
 
 
-port exportObj : () -> Cmd msg
+port exportArtifact : String -> Cmd msg
+
+
+port toggleReconstruction : () -> Cmd msg
+
+
+port undo : () -> Cmd msg
+
+
+port redo : () -> Cmd msg
+
+
+port historyChanged : (Int -> msg) -> Sub msg
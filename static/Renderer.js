@@ -1,4 +1,4 @@
-import { PointCloud, WasmTracker, CameraPath, default as init } from "./wasm-pkg/wasm_vors.js";
+import { PointCloud, WasmTracker, CameraPath, TsdfReconstruction, UndoHistory, TrajectoryBranches, default as init } from "./wasm-pkg/wasm_vors.js";
 
 // WASM stuff ##################################################################
 
@@ -15,17 +15,31 @@ export let last_tracked_frame = 0;
 export let point_cloud;
 export let geometry;
 export let pos_buffer_attr;
-export let nb_particles = 1000000;
+export let color_buffer_attr;
+export let pos_buffer_generation = 0;
+export let nb_particles = 1000000; // Initial capacity, the point cloud grows when needed.
+export let voxel_size = 0; // Keyframe points closer than this (meters) are merged, 0 to keep all.
 export let end_valid = 0;
 export let set_end_valid = (n) => end_valid = n;
 
 // Current frame point cloud.
 export let current_geometry;
 
+// Dense mesh reconstruction, fusing one new keyframe per animation frame.
+export let tsdf;
+
+// Snapshots recorded before each correction, to undo it.
+export let history;
+export let max_undo = 16;
+
+// Trajectories kept when restarting from a previous keyframe.
+export let branches;
+
 // Camera path
 export let camera_path;
-export let camera_path_nb_frames = 10000;
+export let camera_path_nb_frames = 10000; // Initial capacity, the path grows when needed.
 export let camera_pose_attr;
+export let camera_pose_generation = 0;
 export let camera_path_geometry;
 
 // Current camera keyframe pose.
@@ -76,12 +90,18 @@ async function load_wasm() {
 	wasm = await init("./wasm-pkg/wasm_vors_bg.wasm");
 	wasm_tracker = WasmTracker.new();
 	point_cloud = PointCloud.new(nb_particles);
+	point_cloud.set_voxel_size(voxel_size);
 	camera_path = CameraPath.new(camera_path_nb_frames);
+	tsdf = TsdfReconstruction.new();
+	history = UndoHistory.new(max_undo);
+	branches = TrajectoryBranches.new();
 
 	// Bind geometry to THREE buffers.
-	let pos_mem_buffer = getPosMemBuffer(point_cloud, nb_particles);
+	let pos_mem_buffer = getPosMemBuffer(point_cloud);
 	pos_buffer_attr = new THREE.BufferAttribute(pos_mem_buffer, 3).setDynamic(true);
-	scene.add(create_particles(geometry, pos_buffer_attr, 1, 0xffffff));
+	color_buffer_attr = new THREE.BufferAttribute(getColorMemBuffer(point_cloud), 3, true).setDynamic(true);
+	geometry.addAttribute("color", color_buffer_attr);
+	scene.add(create_particles(geometry, pos_buffer_attr, 1, 0xffffff, true));
 
 	// Add a second point cloud for current frame.
 	scene.add(create_particles(current_geometry, pos_buffer_attr, 4, 0xff0000));
@@ -111,8 +131,29 @@ async function load_wasm() {
 	controls.update();
 }
 
-export function chooseP3pInitial(id, base_kf) {
-	let keyframe = wasm_tracker.choose_p3p_initial(id, base_kf);
+// Drop the points of the P3P hypotheses pushed after the last keyframe by p3pVisualize,
+// so that they are not kept in undo snapshots and branches.
+function clearP3pHypotheses() {
+	end_valid = point_cloud.reset_kf(wasm_tracker.nb_keyframes());
+	geometry.setDrawRange(0, end_valid / 3);
+	p3p_point_cloud_1.setDrawRange(0, 0);
+	p3p_point_cloud_2.setDrawRange(0, 0);
+	p3p_point_cloud_3.setDrawRange(0, 0);
+	p3p_point_cloud_4.setDrawRange(0, 0);
+}
+
+// Keep the current trajectory in a branch before a restart, only when it is named.
+function keepBranch(branch_name) {
+	if (branch_name !== "") {
+		branches.keep(branch_name, wasm_tracker, camera_path, point_cloud);
+	}
+}
+
+export function chooseP3pInitial(id, base_kf, branch_name = "") {
+	clearP3pHypotheses();
+	history.record(wasm_tracker, camera_path, point_cloud);
+	keepBranch(branch_name);
+	let keyframe = wasm_tracker.choose_p3p_initial(id, camera_path.index_kf(base_kf));
 	end_valid = point_cloud.reset_kf(keyframe);
 	last_tracked_frame = camera_path.reset_kf(keyframe);
 	let force_keyframe = true;
@@ -120,16 +161,111 @@ export function chooseP3pInitial(id, base_kf) {
 	let section = point_cloud.section(keyframe);
 	geometry.setDrawRange(0, end_valid / 3);
 	updateGeometry(section.start, section.end);
-	// Clear potential P3P poses.
-	p3p_point_cloud_1.setDrawRange(0, 0);
-	p3p_point_cloud_2.setDrawRange(0, 0);
-	p3p_point_cloud_3.setDrawRange(0, 0);
-	p3p_point_cloud_4.setDrawRange(0, 0);
 }
 
-function create_particles(geom, buffer, size, color) {
+// Move a keyframe, its camera and its points to a corrected pose { translation, rotation }.
+export function updateKeyframePose(keyframe, pose) {
+	history.record(wasm_tracker, camera_path, point_cloud);
+	let section = wasm_tracker.update_keyframe_pose(keyframe, pose, camera_path, point_cloud);
+	updateGeometry(section.start, section.end);
+	// Frames tracked from the keyframe, up to the next one, moved with it.
+	let start = camera_path.index_kf(keyframe);
+	let end = keyframe + 1 < wasm_tracker.nb_keyframes() ? camera_path.index_kf(keyframe + 1) : camera_path.len();
+	updateCameraGeometry(3 * start, 3 * end);
+}
+
+// Dense reconstruction is optional: keyframes are fused while tracking
+// only once it is enabled, otherwise when the mesh is exported.
+let live_reconstruction = false;
+
+// Turn fusing keyframes while tracking on or off, return whether it is now on.
+export function toggleReconstruction() {
+	live_reconstruction = !live_reconstruction;
+	return live_reconstruction;
+}
+
+// Fuse the next keyframe not yet in the dense reconstruction, if any.
+function fuseKeyframe() {
+	if (!live_reconstruction || wasm_tracker.nb_keyframes() == 0) { return; }
+	try {
+		tsdf.update(wasm_tracker, 1);
+	} catch (error) {
+		console.error(error);
+	}
+}
+
+// Mesh of the dense reconstruction, after fusing the remaining keyframes,
+// all of them if live reconstruction was off.
+export function exportMesh(binary = true) {
+	tsdf.update(wasm_tracker, wasm_tracker.nb_keyframes());
+	return tsdf.export_ply(binary);
+}
+
+// Serialize the interactive work to continue it later on the same archive.
+export function saveSession(binary = true) {
+	return wasm_tracker.save_session(camera_path, point_cloud, binary);
+}
+
+// Restore a session once the archive is transfered and indexed,
+// instead of initializing the tracker on the first frame.
+export function loadSession(bytes) {
+	let nb_archive_frames = wasm_tracker.load_session(bytes, camera_path, point_cloud);
+	history.clear();
+	branches.free();
+	branches = TrajectoryBranches.new();
+	refreshAll();
+	return nb_archive_frames;
+}
+
+// Branches kept before each restart, [{ id, name, active, nb_frames, nb_keyframes }].
+export function listBranches() {
+	return branches.list(wasm_tracker);
+}
+
+// Continue working on another branch.
+// Snapshots for undo are dropped since they belong to the previous branch.
+// Return the id of the last keyframe of the branch.
+export function switchBranch(id) {
+	branches.switch_to(id, wasm_tracker, camera_path, point_cloud);
+	history.clear();
+	refreshAll();
+	return wasm_tracker.nb_keyframes() - 1;
+}
+
+// Revert the last correction.
+// Return the id of the last keyframe, or -1 if there was nothing to undo.
+export function undo() {
+	if (!history.undo(wasm_tracker, camera_path, point_cloud)) { return -1; }
+	refreshAll();
+	return wasm_tracker.nb_keyframes() - 1;
+}
+
+// Apply again the last reverted correction.
+// Return the id of the last keyframe, or -1 if there was nothing to redo.
+export function redo() {
+	if (!history.redo(wasm_tracker, camera_path, point_cloud)) { return -1; }
+	refreshAll();
+	return wasm_tracker.nb_keyframes() - 1;
+}
+
+// Transfer the whole point cloud and camera path after they were replaced.
+function refreshAll() {
+	end_valid = point_cloud.end_valid();
+	geometry.setDrawRange(0, end_valid / 3);
+	updateGeometry(0, end_valid);
+	last_tracked_frame = camera_path.len() - 1;
+	camera_path_geometry.setDrawRange(0, camera_path.len());
+	updateCameraGeometry(0, 3 * camera_path.len());
+}
+
+function create_particles(geom, buffer, size, color, vertex_colors = false) {
 	geom.addAttribute("position", buffer);
-	let material = new THREE.PointsMaterial({size: size, sizeAttenuation: false, color: color});
+	let material = new THREE.PointsMaterial({
+		size: size,
+		sizeAttenuation: false,
+		color: color,
+		vertexColors: vertex_colors ? THREE.VertexColors : THREE.NoColors,
+	});
 	let particles = new THREE.Points(geom, material);
 	particles.frustumCulled = false;
 	return particles;
@@ -138,13 +274,14 @@ function create_particles(geom, buffer, size, color) {
 export function pickReference(index) {
 	wasm_tracker.pick_reference_kf_data(index);
 	let ptr = wasm_tracker.reference_keyframe_data();
-	let data = new Uint8ClampedArray(wasm.memory.buffer, ptr, 4 * 320 * 240);
-	let image_data = new ImageData(data, 320, 240);
-	canvas_2d_ctx_ref.putImageData(image_data, 0, 0);
+	putKeyframeImage(canvas_2d_ctx_ref, ptr);
 }
 
-export function restartFromKeyframe(baseKf, keyframe) {
+export function restartFromKeyframe(baseKf, keyframe, branch_name = "") {
 	assert(baseKf < keyframe, "Base keyframe >= restart keyframe");
+	clearP3pHypotheses();
+	history.record(wasm_tracker, camera_path, point_cloud);
+	keepBranch(branch_name);
 	end_valid = point_cloud.reset_kf(keyframe);
 	last_tracked_frame = camera_path.reset_kf(keyframe);
 	let base_frame = camera_path.index_kf(baseKf);
@@ -155,35 +292,62 @@ export function restartFromKeyframe(baseKf, keyframe) {
 	track(force_keyframe);
 }
 
-// export function restartFromKeyframeP3p(baseKf, keyframe, p3p_ref_points, p3p_key_points) {
-// 	assert(baseKf < keyframe, "Base keyframe >= restart keyframe");
-// 	end_valid = point_cloud.reset_kf(keyframe);
-// 	last_tracked_frame = camera_path.reset_kf(keyframe);
-// 	let base_frame = camera_path.index_kf(baseKf);
-// 	wasm_tracker.reset_at_p3p(base_frame, last_tracked_frame, keyframe, p3p_ref_points, p3p_key_points);
-// 	geometry.setDrawRange(0, end_valid / 3);
-// 	camera_path_geometry.setDrawRange(0, last_tracked_frame);
-// 	let force_keyframe = true;
-// 	track(force_keyframe);
-// }
+// Restart from a keyframe with a pose computed by P3P from 3 pairs of clicked points,
+// or by robust PnP with more pairs and an optional config (see solvePnp),
+// choosing the most probable hypothesis unless one is given.
+// Return { keyframe_id, hypothesis, probabilities, rejected }.
+export function restartFromKeyframeP3p(baseKf, keyframe, p3p_ref_points, p3p_key_points, hypothesis, config, branch_name = "") {
+	assert(baseKf < keyframe, "Base keyframe >= restart keyframe");
+	clearP3pHypotheses();
+	let base_frame = camera_path.index_kf(baseKf);
+	let keyframe_frame = camera_path.index_kf(keyframe);
+	// Hypotheses are checked before saving the state,
+	// so that a rejected restart leaves no undo entry nor branch.
+	let reset = wasm_tracker.prepare_p3p(base_frame, keyframe_frame, p3p_ref_points, p3p_key_points, hypothesis, config);
+	history.record(wasm_tracker, camera_path, point_cloud);
+	keepBranch(branch_name);
+	wasm_tracker.choose_p3p_initial(reset.hypothesis, base_frame);
+	end_valid = point_cloud.reset_kf(reset.keyframe_id);
+	last_tracked_frame = camera_path.reset_kf(reset.keyframe_id);
+	let force_keyframe = true;
+	track(force_keyframe);
+	let section = point_cloud.section(reset.keyframe_id);
+	geometry.setDrawRange(0, end_valid / 3);
+	updateGeometry(section.start, section.end);
+	return reset;
+}
+
+// Estimate the pose of the last keyframe from 3 or more pairs of clicked points,
+// rejecting mis-clicks. Return { pose, inliers, rejected, errors }.
+export function solvePnp(baseKf, p3p_ref_points, p3p_key_points, config) {
+	let base_frame = camera_path.index_kf(baseKf);
+	return wasm_tracker.solve_pnp(base_frame, p3p_ref_points, p3p_key_points, config);
+}
 
-export function p3pVisualize(baseKf, keyframe, p3p_ref_points, p3p_key_points) {
+// Show the points of the keyframe seen from each P3P hypothesis,
+// with an optional robust PnP config for more than 3 pairs (see solvePnp).
+// Return { probabilities, rejected }.
+export function p3pVisualize(baseKf, keyframe, p3p_ref_points, p3p_key_points, config) {
 	assert(baseKf < keyframe, "Base keyframe >= restart keyframe");
+	clearP3pHypotheses();
 	let base_frame = camera_path.index_kf(baseKf);
-	let probabilities = wasm_tracker.p3p_visualize(base_frame, last_tracked_frame, p3p_ref_points, p3p_key_points, point_cloud);
-	let nb_p3p = probabilities.length - 1;
+	let keyframe_frame = camera_path.index_kf(keyframe);
+	let result = wasm_tracker.p3p_visualize(base_frame, keyframe_frame, p3p_ref_points, p3p_key_points, config, point_cloud);
+	let nb_p3p = result.probabilities.length - 1;
+	// Hypotheses sections come after the last keyframe.
+	let first = wasm_tracker.nb_keyframes();
 	// Update geometry draw range.
-	if (nb_p3p >= 1) { updateDrawRange(p3p_point_cloud_1, point_cloud.section(keyframe + 1)); }
-	if (nb_p3p >= 2) { updateDrawRange(p3p_point_cloud_2, point_cloud.section(keyframe + 2)); }
-	if (nb_p3p >= 3) { updateDrawRange(p3p_point_cloud_3, point_cloud.section(keyframe + 3)); }
-	if (nb_p3p >= 4) { updateDrawRange(p3p_point_cloud_4, point_cloud.section(keyframe + 4)); }
+	if (nb_p3p >= 1) { updateDrawRange(p3p_point_cloud_1, point_cloud.section(first)); }
+	if (nb_p3p >= 2) { updateDrawRange(p3p_point_cloud_2, point_cloud.section(first + 1)); }
+	if (nb_p3p >= 3) { updateDrawRange(p3p_point_cloud_3, point_cloud.section(first + 2)); }
+	if (nb_p3p >= 4) { updateDrawRange(p3p_point_cloud_4, point_cloud.section(first + 3)); }
 	// Transfer geometry to GPU.
 	if (nb_p3p > 0) {
-		let first_section = point_cloud.section(keyframe + 1);
-		let last_section = point_cloud.section(keyframe + nb_p3p);
+		let first_section = point_cloud.section(first);
+		let last_section = point_cloud.section(first + nb_p3p - 1);
 		updateGeometry(first_section.start, last_section.end);
 	}
-	return probabilities;
+	return result;
 }
 
 function assert(condition, message) {
@@ -193,21 +357,59 @@ function assert(condition, message) {
 function updateCurrentKfImage(index) {
 	wasm_tracker.pick_current_kf_data(index);
 	let kf_img_data_ptr = wasm_tracker.current_keyframe_data();
-	let kf_img_data = new Uint8ClampedArray(wasm.memory.buffer, kf_img_data_ptr, 4 * 320 * 240);
-	let image_data = new ImageData(kf_img_data, 320, 240);
-	canvas_2d_ctx.putImageData(image_data, 0, 0);
+	putKeyframeImage(canvas_2d_ctx, kf_img_data_ptr);
+}
+
+// Draw a keyframe RGBA buffer, resizing the canvas to the keyframe dimensions.
+function putKeyframeImage(ctx, ptr) {
+	let width = wasm_tracker.keyframe_width();
+	let height = wasm_tracker.keyframe_height();
+	let data = new Uint8ClampedArray(wasm.memory.buffer, ptr, 4 * width * height);
+	if (ctx.canvas.width != width || ctx.canvas.height != height) {
+		ctx.canvas.width = width;
+		ctx.canvas.height = height;
+	}
+	ctx.putImageData(new ImageData(data, width, height), 0, 0);
 }
 
 function getCameraPoseBuffer() {
-	return new Float32Array(wasm.memory.buffer, camera_path.poses(), 3 * camera_path_nb_frames);
+	return new Float32Array(wasm.memory.buffer, camera_path.poses(), 3 * camera_path.capacity());
 }
 
-export function exportCameraPoses() {
-	return new Float32Array(wasm.memory.buffer, camera_path.poses(), 3 * 2000);
+// Bind a new camera pose attribute to the camera geometries
+// when the camera path buffer was reallocated by wasm.
+function rebindCameraPoseBuffer() {
+	camera_pose_attr = new THREE.BufferAttribute(getCameraPoseBuffer(), 3).setDynamic(true);
+	camera_path_geometry.addAttribute("position", camera_pose_attr);
+	current_camera_path_geometry.addAttribute("position", camera_pose_attr);
+	camera_pose_generation = camera_path.generation();
 }
 
-export function getPosMemBuffer(point_cloud, nb_particles) {
-	return new Float32Array(wasm.memory.buffer, point_cloud.points(), 3 * nb_particles);
+export function getPosMemBuffer(point_cloud) {
+	return new Float32Array(wasm.memory.buffer, point_cloud.points(), 3 * point_cloud.capacity());
+}
+
+export function getColorMemBuffer(point_cloud) {
+	return new Uint8Array(wasm.memory.buffer, point_cloud.colors(), 3 * point_cloud.capacity());
+}
+
+// Bind a new position attribute to all point geometries
+// when the point cloud buffer was reallocated by wasm.
+function rebindPosBuffer() {
+	pos_buffer_attr = new THREE.BufferAttribute(getPosMemBuffer(point_cloud), 3).setDynamic(true);
+	let point_geometries = [
+		geometry,
+		current_geometry,
+		p3p_point_cloud_1,
+		p3p_point_cloud_2,
+		p3p_point_cloud_3,
+		p3p_point_cloud_4,
+	];
+	point_geometries.forEach(geom => geom.addAttribute("position", pos_buffer_attr));
+	color_buffer_attr = new THREE.BufferAttribute(getColorMemBuffer(point_cloud), 3, true).setDynamic(true);
+	geometry.addAttribute("color", color_buffer_attr);
+	pos_buffer_generation = point_cloud.generation();
+	console.log(`Point cloud buffer reallocated (${point_cloud.memory_usage()} bytes)`);
 }
 
 function updateCurrentCameraPoseKf(frame) {
@@ -223,6 +425,7 @@ function updateDrawRange(geom, section) {
 }
 
 function renderLoop() {
+	fuseKeyframe();
 	controls.update();
 	renderer.render(scene, camera);
 	window.requestAnimationFrame(renderLoop);
@@ -241,6 +444,8 @@ function trackFrame(force_keyframe, frame_id, nb_frames) {
 		// update point cloud.
 		let start_update = end_valid;
 		end_valid = point_cloud.tick(wasm_tracker);
+		// Points of previous keyframes may have been merged with new ones.
+		start_update = Math.min(start_update, point_cloud.take_modified_start());
 		geometry.setDrawRange(0, end_valid / 3);
 		updateGeometry(start_update, end_valid);
 		// update camera path.
@@ -251,6 +456,11 @@ function trackFrame(force_keyframe, frame_id, nb_frames) {
 }
 
 export function updateCameraGeometry(start, end) {
+	if (camera_path.generation() != camera_pose_generation) {
+		// A new attribute is fully transfered to GPU.
+		rebindCameraPoseBuffer();
+		return;
+	}
 	let nb_update = end - start;
 	if (nb_update > 0) {
 		// Update buffers because wasm memory might grow.
@@ -262,18 +472,29 @@ export function updateCameraGeometry(start, end) {
 }
 
 export function updateGeometry(start, end) {
-	updateGeometryDetail(point_cloud, nb_particles, pos_buffer_attr, start, end);
+	if (point_cloud.generation() != pos_buffer_generation) {
+		// A new attribute is fully transfered to GPU.
+		rebindPosBuffer();
+		return;
+	}
+	updateGeometryDetail(point_cloud, pos_buffer_attr, start, end);
 }
 
-function updateGeometryDetail(point_cloud, nb_particles, pos_attr, start, end) {
+function updateGeometryDetail(point_cloud, pos_attr, start, end) {
 	let nb_update = end - start;
 	if (nb_update > 0) {
 		// Update buffers because wasm memory might grow.
-		pos_attr.setArray(getPosMemBuffer(point_cloud, nb_particles));
+		pos_attr.setArray(getPosMemBuffer(point_cloud));
 
 		pos_attr.updateRange.offset = start;
 		pos_attr.updateRange.count = nb_update;
 		pos_attr.needsUpdate = true;
+
+		// Colors have the same layout than positions.
+		color_buffer_attr.setArray(getColorMemBuffer(point_cloud));
+		color_buffer_attr.updateRange.offset = start;
+		color_buffer_attr.updateRange.count = nb_update;
+		color_buffer_attr.needsUpdate = true;
 	}
 }
 
@@ -14,9 +14,13 @@ export function activatePorts(app, containerSize) {
 	startAnimationFrameLoop(app.ports.animationFrame);
 
 	app.ports.track.subscribe( () => {
-		let has_tracked = Renderer.track();
-		if (has_tracked && Renderer.wasm_tracker.change_keyframe) {
-			app.ports.newKeyFrame.send(0);
+		try {
+			let has_tracked = Renderer.track();
+			if (has_tracked && Renderer.wasm_tracker.change_keyframe) {
+				app.ports.newKeyFrame.send(0);
+			}
+		} catch (error) {
+			logWasmError(error);
 		}
 	});
 	
@@ -29,43 +33,99 @@ export function activatePorts(app, containerSize) {
 	});
 	
 	app.ports.p3pVisualize.subscribe( data => {
-		let probabilities = Renderer.p3pVisualize(
+		let result = Renderer.p3pVisualize(
 			data.reference,
 			data.restartFrom,
 			data.p3pRef,
 			data.p3pKey,
 		);
 		// Send probabilities to the elm app.
-		app.ports.p3pProbabilities.send(probabilities);
+		app.ports.p3pProbabilities.send(result.probabilities);
 	});
 
 	app.ports.chooseP3pInitial.subscribe( ({id: id, base_kf: base_kf}) => {
 		Renderer.chooseP3pInitial(id, base_kf);
 	});
 
-	app.ports.exportObj.subscribe( () => {
-		// let obj_vec = [];
-		// let pos_buffer = Renderer.getPosMemBuffer(Renderer.point_cloud, Renderer.nb_particles);
-		// for (let i = 0; i < Renderer.end_valid; i += 3) {
-		// 	obj_vec.push(`v ${pos_buffer[i]} ${pos_buffer[i+1]} ${pos_buffer[i+2]}`);
-		// }
-		let camera_pos_vec = [];
-		let pos_buffer = Renderer.exportCameraPoses();
-		for (let i = 0; i < pos_buffer.length; i += 3) {
-			camera_pos_vec.push(`${Math.round(i/3)} ${pos_buffer[i]} ${pos_buffer[i+1]} ${pos_buffer[i+2]} 0 0 0 1`);
+	// Undo and redo corrections, then resynchronize the keyframes timeline.
+	app.ports.undo.subscribe( () => {
+		let last_keyframe = Renderer.undo();
+		if (last_keyframe >= 0) {
+			app.ports.historyChanged.send(last_keyframe);
+		}
+	});
+
+	app.ports.redo.subscribe( () => {
+		let last_keyframe = Renderer.redo();
+		if (last_keyframe >= 0) {
+			app.ports.historyChanged.send(last_keyframe);
+		}
+	});
+
+	// Each exported artifact is downloaded on its own.
+	const exporters = {
+		point_cloud: () => {
+			let binary = true;
+			let with_normals = true;
+			downloadBytes('point_cloud.ply', Renderer.point_cloud.export_ply(binary, with_normals));
+		},
+		scene: () => {
+			let frustum_depth = 0.1;
+			download('scene.obj', Renderer.point_cloud.export_obj(Renderer.wasm_tracker, frustum_depth));
+		},
+		mesh: () => {
+			let binary = true;
+			downloadBytes('mesh.ply', Renderer.exportMesh(binary));
+		},
+		trajectory: () => {
+			download('camera_path.txt', Renderer.camera_path.export_tum_trajectory());
+			for (let branch of Renderer.listBranches()) {
+				if (!branch.active) {
+					let trajectory = Renderer.branches.export_tum_trajectory(branch.id, Renderer.camera_path);
+					download(`camera_path_${branch.name}.txt`, trajectory);
+				}
+			}
+		},
+		session: () => downloadBytes('session.vors', Renderer.saveSession()),
+		edit_log: () => download('edit_log.json', Renderer.wasm_tracker.edit_log()),
+	};
+
+	app.ports.toggleReconstruction.subscribe( () => {
+		let enabled = Renderer.toggleReconstruction();
+		console.log(`Live reconstruction ${enabled ? "enabled" : "disabled"}`);
+	});
+
+	app.ports.exportArtifact.subscribe( artifact => {
+		try {
+			exporters[artifact]();
+		} catch (error) {
+			logWasmError(error);
 		}
-		let camera_path = camera_pos_vec.join('\n');
-		download('camera_path.txt', camera_path);
 	});
 	
-	// Set up file reader.
-	let file_reader = new FileReader();
 	let camera_model = "icl";
-	file_reader.onload = () => {
-		console.log("Transfering tar data to wasm memory ...");
-		transferContent(file_reader.result);
+	let session_file = null;
+
+	// Report an error of init or load_session to the elm app, so that another
+	// dataset, camera or session can be tried.
+	function loadingFailed(error) {
+		logWasmError(error);
+		if (error && error.code) {
+			app.ports.loadingFailed.send({ code: error.code, message: error.message });
+		} else {
+			app.ports.loadingFailed.send({ code: "unknown", message: String(error) });
+		}
+	}
+
+	function initTracker() {
 		console.log("Initializing tracker with first image ...");
-		const nb_frames = Renderer.wasm_tracker.init(camera_model);
+		let nb_frames;
+		try {
+			nb_frames = Renderer.wasm_tracker.init(camera_model);
+		} catch (error) {
+			loadingFailed(error);
+			return;
+		}
 		console.log("Rendering first frame point cloud ...");
 		// Update point cloud.
 		let start_valid = Renderer.end_valid;
@@ -79,14 +139,48 @@ export function activatePorts(app, containerSize) {
 		Renderer.updateCameraGeometry(0, 3);
 		// Render.
 		Renderer.renderer.render(Renderer.scene, Renderer.camera);
-		app.ports.datasetLoaded.send(nb_frames);
-		file_reader = null; // Free memory.
+		app.ports.datasetLoaded.send({ nbFrames: nb_frames, lastKeyframe: 0 });
+	}
+
+	// Continue the work saved in a session file instead of starting from the first frame.
+	function restoreSession(bytes) {
+		console.log("Restoring session ...");
+		let nb_frames;
+		try {
+			nb_frames = Renderer.loadSession(bytes);
+		} catch (error) {
+			loadingFailed(error);
+			return;
+		}
+		Renderer.renderer.render(Renderer.scene, Renderer.camera);
+		let last_keyframe = Renderer.wasm_tracker.nb_keyframes() - 1;
+		app.ports.datasetLoaded.send({ nbFrames: nb_frames, lastKeyframe: last_keyframe });
 	}
 
 	// Transfer archive data to wasm when the file is loaded.
-	app.ports.loadDataset.subscribe(({file: archive, camera: cam}) => {
+	// A new reader is used for each attempt, and freed with its content once done.
+	app.ports.loadDataset.subscribe(({file: archive, camera: cam, session: session}) => {
 		camera_model = cam;
+		session_file = session;
 		console.log("Loading tar archive ...");
+		let file_reader = new FileReader();
+		file_reader.onload = () => {
+			console.log("Transfering tar data to wasm memory ...");
+			try {
+				transferContent(file_reader.result);
+			} catch (error) {
+				// Unsupported or corrupt archive.
+				loadingFailed(error);
+				return;
+			}
+			if (session_file) {
+				let session_reader = new FileReader();
+				session_reader.onload = () => restoreSession(new Uint8Array(session_reader.result));
+				session_reader.readAsArrayBuffer(session_file);
+			} else {
+				initTracker();
+			}
+		};
 		file_reader.readAsArrayBuffer(archive);
 	});
 
@@ -114,6 +208,27 @@ function startAnimationFrameLoop(port) {
 	loop();
 }
 
+// Errors thrown by wasm are objects of the shape { code, message }.
+function logWasmError(error) {
+	if (error && error.code) {
+		console.error(`[${error.code}] ${error.message}`);
+	} else {
+		console.error(error);
+	}
+}
+
+function downloadBytes(filename, bytes) {
+	let url = URL.createObjectURL(new Blob([bytes], {type: 'application/octet-stream'}));
+	var element = document.createElement('a');
+	element.setAttribute('href', url);
+	element.setAttribute('download', filename);
+	element.style.display = 'none';
+	document.body.appendChild(element);
+	element.click();
+	document.body.removeChild(element);
+	URL.revokeObjectURL(url);
+}
+
 function download(filename, text) {
 	var element = document.createElement('a');
 	element.setAttribute('href', 'data:text/plain;charset=utf-8,' + encodeURIComponent(text));
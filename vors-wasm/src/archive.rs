@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use crate::error::TrackerError;
+
+/// Location of a file inside the in-memory archive buffer.
+pub struct FileEntry {
+    pub offset: usize,
+    pub length: usize,
+}
+
+/// Container format of an archive, detected from its magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGzip,
+    TarZstd,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn detect(buffer: &[u8]) -> ArchiveFormat {
+        if buffer.starts_with(&[0x1f, 0x8b]) {
+            ArchiveFormat::TarGzip
+        } else if buffer.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            ArchiveFormat::TarZstd
+        } else if buffer.starts_with(b"PK\x03\x04") || buffer.starts_with(b"PK\x05\x06") {
+            ArchiveFormat::Zip
+        } else {
+            ArchiveFormat::Tar
+        }
+    }
+}
+
+/// Index the entries of an uncompressed tar archive, without copying them.
+pub fn tar_entries(buffer: &[u8]) -> Result<HashMap<String, FileEntry>, TrackerError> {
+    let mut entries = HashMap::new();
+    let mut archive = tar::Archive::new(buffer);
+    for file in archive.entries().map_err(archive_err)? {
+        // Check for an I/O error.
+        let file = file.map_err(archive_err)?;
+        let path = file.path().map_err(archive_err)?;
+        let name = entry_name(&path)?.to_owned();
+        let length = file.header().entry_size().map_err(archive_err)? as usize;
+        entries.insert(
+            name,
+            FileEntry {
+                offset: file.raw_file_position() as usize,
+                length,
+            },
+        );
+    }
+    Ok(entries)
+}
+
+/// Decompress a tar archive in a single streaming pass.
+/// Only the content of the entries is kept (no tar headers nor padding),
+/// and the returned entries index into the returned buffer.
+///
+/// Compressed streams cannot be read at an arbitrary offset,
+/// so the whole archive is inflated up front instead of on demand.
+/// The compressed and decompressed buffers coexist until this returns,
+/// and both must fit in the 4 GiB address space of wasm32
+/// (less in browsers limiting the WebAssembly memory).
+/// Larger sequences should be split or stored uncompressed.
+pub fn inflate_tar_entries(
+    buffer: &[u8],
+    format: ArchiveFormat,
+) -> Result<(Vec<u8>, HashMap<String, FileEntry>), TrackerError> {
+    match format {
+        ArchiveFormat::Tar | ArchiveFormat::Zip => Err(TrackerError::Archive(format!(
+            "{:?} is not a compressed tar archive",
+            format
+        ))),
+        ArchiveFormat::TarGzip => read_tar_entries(flate2::read::GzDecoder::new(buffer)),
+        ArchiveFormat::TarZstd => {
+            let mut source = buffer;
+            let decoder = ruzstd::StreamingDecoder::new(&mut source)
+                .map_err(|e| TrackerError::Archive(format!("Invalid zstd archive: {}", e)))?;
+            read_tar_entries(decoder)
+        }
+    }
+}
+
+fn read_tar_entries<R: Read>(
+    reader: R,
+) -> Result<(Vec<u8>, HashMap<String, FileEntry>), TrackerError> {
+    let mut content = Vec::new();
+    let mut entries = HashMap::new();
+    let mut archive = tar::Archive::new(reader);
+    for file in archive.entries().map_err(archive_err)? {
+        let mut file = file.map_err(archive_err)?;
+        let name = entry_name(&file.path().map_err(archive_err)?)?.to_owned();
+        let offset = content.len();
+        let length = file.read_to_end(&mut content).map_err(archive_err)?;
+        entries.insert(name, FileEntry { offset, length });
+    }
+    Ok((content, entries))
+}
+
+/// Index the entries of a zip archive.
+/// Stored entries are referenced in place. Other entries are decompressed
+/// into the returned buffer, meant to be appended to the archive buffer,
+/// and their offsets already account for it.
+pub fn zip_entries(buffer: &[u8]) -> Result<(Vec<u8>, HashMap<String, FileEntry>), TrackerError> {
+    let zip_err = |e: zip::result::ZipError| TrackerError::Archive(e.to_string());
+    let mut archive = zip::ZipArchive::new(Cursor::new(buffer)).map_err(zip_err)?;
+    let mut inflated = Vec::new();
+    let mut entries = HashMap::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(zip_err)?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.name().to_owned();
+        let entry = match file.compression() {
+            zip::CompressionMethod::Stored => FileEntry {
+                offset: file.data_start() as usize,
+                length: file.size() as usize,
+            },
+            _ => {
+                let offset = buffer.len() + inflated.len();
+                let length = file.read_to_end(&mut inflated).map_err(archive_err)?;
+                FileEntry { offset, length }
+            }
+        };
+        entries.insert(name, entry);
+    }
+    Ok((inflated, entries))
+}
+
+fn archive_err(e: std::io::Error) -> TrackerError {
+    TrackerError::Archive(e.to_string())
+}
+
+/// Retrieve the content of a file in the archive.
+pub fn get_buffer<'a>(
+    name: &str,
+    file: &'a [u8],
+    entries: &HashMap<String, FileEntry>,
+) -> Result<&'a [u8], TrackerError> {
+    let entry = entries
+        .get(name)
+        .ok_or_else(|| TrackerError::Archive(format!("Entry {} is not in archive", name)))?;
+    file.get(entry.offset..entry.offset + entry.length)
+        .ok_or_else(|| TrackerError::Archive(format!("Entry {} is truncated", name)))
+}
+
+/// Convert the path of an archive entry into a key of the entries map.
+pub fn entry_name(path: &Path) -> Result<&str, TrackerError> {
+    path.to_str()
+        .ok_or_else(|| TrackerError::Archive(format!("Invalid entry path: {:?}", path)))
+}
@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+
+use crate::error::TrackerError;
+use visual_odometry_rs::dataset::tum_rgbd::Association;
+
+/// Default maximum time difference (in seconds) between associated
+/// color and depth images, same as TUM associate.py.
+pub const DEFAULT_MAX_DIFFERENCE: f64 = 0.02;
+
+/// Check that a maximum time difference can be used to associate images.
+pub fn check_max_difference(max_difference: f64) -> Result<(), TrackerError> {
+    if max_difference.is_finite() && max_difference > 0.0 {
+        Ok(())
+    } else {
+        Err(TrackerError::Config(format!(
+            "Maximum association difference must be finite and strictly positive, got {}",
+            max_difference
+        )))
+    }
+}
+
+/// Parse a TUM file list such as rgb.txt or depth.txt
+/// into a list of (timestamp, file path).
+pub fn parse_file_list(content: &str) -> Result<Vec<(f64, String)>, TrackerError> {
+    let mut list = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let (timestamp, path) = match (words.next(), words.next()) {
+            (Some(t), Some(p)) => (t, p),
+            _ => {
+                return Err(TrackerError::Decode(format!(
+                    "Invalid file list line: {}",
+                    line
+                )));
+            }
+        };
+        let invalid = || TrackerError::Decode(format!("Invalid timestamp: {}", timestamp));
+        let timestamp: f64 = timestamp.parse().map_err(|_| invalid())?;
+        if !timestamp.is_finite() {
+            return Err(invalid());
+        }
+        list.push((timestamp, path.to_owned()));
+    }
+    Ok(list)
+}
+
+/// Associate color and depth images by nearest timestamps.
+///
+/// Like TUM associate.py, candidate pairs closer than `max_difference`
+/// are matched greedily from the closest, each image being used at most once.
+/// Associations are sorted by color timestamp.
+pub fn associate(
+    color: &[(f64, String)],
+    depth: &[(f64, String)],
+    max_difference: f64,
+) -> Vec<Association> {
+    let mut depth_sorted: Vec<(usize, f64)> = depth
+        .iter()
+        .enumerate()
+        .map(|(i, &(t, _))| (i, t))
+        .collect();
+    depth_sorted.sort_by(|(_, t1), (_, t2)| t1.total_cmp(t2));
+
+    // Gather all candidate pairs within the time window.
+    let mut candidates = Vec::new();
+    for (i, &(t_color, _)) in color.iter().enumerate() {
+        let lower = t_color - max_difference;
+        let start = match depth_sorted.binary_search_by(|(_, t)| t.total_cmp(&lower)) {
+            Ok(k) | Err(k) => k,
+        };
+        for &(j, t_depth) in depth_sorted[start..].iter() {
+            let diff = (t_depth - t_color).abs();
+            if t_depth > t_color + max_difference {
+                break;
+            } else if diff < max_difference {
+                candidates.push((diff, i, j));
+            }
+        }
+    }
+    candidates.sort_by(|(d1, _, _), (d2, _, _)| d1.total_cmp(d2));
+
+    // Greedily match the closest pairs.
+    let mut color_used = vec![false; color.len()];
+    let mut depth_used = vec![false; depth.len()];
+    let mut matches = Vec::new();
+    for (_, i, j) in candidates {
+        if !color_used[i] && !depth_used[j] {
+            color_used[i] = true;
+            depth_used[j] = true;
+            matches.push((i, j));
+        }
+    }
+    matches.sort_by(|(i1, _), (i2, _)| color[*i1].0.total_cmp(&color[*i2].0));
+
+    matches
+        .into_iter()
+        .map(|(i, j)| Association {
+            depth_timestamp: depth[j].0,
+            depth_file_path: PathBuf::from(&depth[j].1),
+            color_timestamp: color[i].0,
+            color_file_path: PathBuf::from(&color[i].1),
+        })
+        .collect()
+}
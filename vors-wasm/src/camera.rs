@@ -0,0 +1,245 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use wasm_bindgen::JsValue;
+
+use crate::error::TrackerError;
+use nalgebra::{Point3, Vector3};
+use visual_odometry_rs::core::camera::Intrinsics;
+use visual_odometry_rs::dataset::tum_rgbd;
+use visual_odometry_rs::misc::type_aliases::Iso3;
+
+/// Names of the calibration files looked for at the root of an archive.
+pub const CALIBRATION_FILES: [&str; 2] = ["camera.yaml", "calib.txt"];
+
+/// Camera calibration of an RGB-D sensor.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Calibration {
+    pub fx: f32,
+    pub fy: f32,
+    pub cx: f32,
+    pub cy: f32,
+    /// Factor converting depth map values into meters.
+    #[serde(default = "default_depth_scale")]
+    pub depth_scale: f32,
+    /// Resolution of the calibration, intrinsics are scaled for other image sizes.
+    #[serde(default)]
+    pub width: Option<usize>,
+    #[serde(default)]
+    pub height: Option<usize>,
+}
+
+fn default_depth_scale() -> f32 {
+    tum_rgbd::DEPTH_SCALE
+}
+
+impl Calibration {
+    /// Named calibrations of the TUM RGB-D and ICL-NUIM datasets.
+    pub fn preset(camera_id: &str) -> Result<Calibration, TrackerError> {
+        let intrinsics = match camera_id {
+            "fr1" => tum_rgbd::INTRINSICS_FR1,
+            "fr2" => tum_rgbd::INTRINSICS_FR2,
+            "fr3" => tum_rgbd::INTRINSICS_FR3,
+            "icl" => tum_rgbd::INTRINSICS_ICL_NUIM,
+            _ => {
+                return Err(TrackerError::Config(format!(
+                    "Unknown camera id: {}",
+                    camera_id
+                )));
+            }
+        };
+        Ok(Calibration {
+            fx: intrinsics.focal.0,
+            fy: intrinsics.focal.1,
+            cx: intrinsics.principal_point.0,
+            cy: intrinsics.principal_point.1,
+            depth_scale: tum_rgbd::DEPTH_SCALE,
+            width: Some(640),
+            height: Some(480),
+        })
+    }
+
+    /// Read a calibration sent from JavaScript.
+    /// It is either the name of a preset ("fr1", "fr2", "fr3", "icl")
+    /// or an object `{ fx, fy, cx, cy, depth_scale?, width?, height? }`.
+    pub fn from_js(value: JsValue) -> Result<Calibration, TrackerError> {
+        if let Some(camera_id) = value.as_string() {
+            return Calibration::preset(&camera_id);
+        }
+        if value.is_undefined() || value.is_null() {
+            return Err(TrackerError::Config(
+                "No calibration file in archive and no camera given".to_owned(),
+            ));
+        }
+        let calibration: Calibration = serde_wasm_bindgen::from_value(value)
+            .map_err(|e| TrackerError::Config(format!("Invalid camera intrinsics: {}", e)))?;
+        calibration.validate()?;
+        Ok(calibration)
+    }
+
+    /// Parse the content of a calibration file.
+    ///
+    /// Two layouts are accepted:
+    ///
+    /// * key/value lines such as `fx: 525.0` or `fx = 525.0`
+    ///   (prefixes like `Camera.fx` are ignored, so are non numeric values),
+    ///   with keys fx, fy, cx, cy, depth_scale, width and height.
+    /// * whitespace separated numbers in the order
+    ///   `fx fy cx cy [width height [depth_scale]]`.
+    ///
+    /// Lines starting with `#` are comments.
+    pub fn parse(content: &str) -> Result<Calibration, TrackerError> {
+        let mut values: HashMap<String, f32> = HashMap::new();
+        let mut positional: Vec<f32> = Vec::new();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() || line.starts_with('%') || line == "---" {
+                continue;
+            }
+            match line.find([':', '=']) {
+                Some(i) => {
+                    let key = line[..i].trim().rsplit('.').next().unwrap_or("");
+                    if let Ok(value) = line[i + 1..].trim().parse() {
+                        values.insert(key.to_lowercase(), value);
+                    }
+                }
+                None => {
+                    for word in line.split_whitespace() {
+                        let value = word.parse().map_err(|_| {
+                            TrackerError::Decode(format!("Invalid number in calibration: {}", word))
+                        })?;
+                        positional.push(value);
+                    }
+                }
+            }
+        }
+
+        // Positional numbers are only used when there is no key.
+        if values.is_empty() {
+            let names = ["fx", "fy", "cx", "cy", "width", "height", "depth_scale"];
+            values = names
+                .iter()
+                .zip(positional.iter())
+                .map(|(name, &v)| (name.to_string(), v))
+                .collect();
+        }
+
+        let get = |key: &str| {
+            values
+                .get(key)
+                .cloned()
+                .ok_or_else(|| TrackerError::Decode(format!("Missing {} in calibration", key)))
+        };
+        let get_size = |key: &str| match values.get(key) {
+            None => Ok(None),
+            Some(&v) if v.is_finite() && v >= 1.0 && v.fract() == 0.0 => Ok(Some(v as usize)),
+            Some(&v) => Err(TrackerError::Config(format!(
+                "Calibration {} must be a strictly positive integer, got {}",
+                key, v
+            ))),
+        };
+        let calibration = Calibration {
+            fx: get("fx")?,
+            fy: get("fy")?,
+            cx: get("cx")?,
+            cy: get("cy")?,
+            depth_scale: get("depth_scale").unwrap_or_else(|_| default_depth_scale()),
+            width: get_size("width")?,
+            height: get_size("height")?,
+        };
+        calibration.validate()?;
+        Ok(calibration)
+    }
+
+    /// Check the intrinsics, depth scale and resolution of a calibration.
+    pub fn validate(&self) -> Result<(), TrackerError> {
+        let positive = |x: f32| x.is_finite() && x > 0.0;
+        if !positive(self.fx) || !positive(self.fy) {
+            return Err(TrackerError::Config(
+                "Focal lengths must be finite and strictly positive".to_owned(),
+            ));
+        }
+        if !self.cx.is_finite() || !self.cy.is_finite() {
+            return Err(TrackerError::Config(
+                "Principal point must be finite".to_owned(),
+            ));
+        }
+        if !positive(self.depth_scale) {
+            return Err(TrackerError::Config(
+                "Depth scale must be finite and strictly positive".to_owned(),
+            ));
+        }
+        match (self.width, self.height) {
+            (Some(width), Some(height)) if width == 0 || height == 0 => Err(TrackerError::Config(
+                "Calibration width and height must be strictly positive".to_owned(),
+            )),
+            (Some(_), None) | (None, Some(_)) => Err(TrackerError::Config(
+                "Calibration must give both width and height, or none of them".to_owned(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adapt the calibration to the resolution of the images.
+    ///
+    /// A calibration done at another resolution with the same aspect ratio,
+    /// such as a preset for downsampled images, has its intrinsics scaled.
+    /// Return whether they were scaled.
+    pub fn fit_resolution(&mut self, width: usize, height: usize) -> Result<bool, TrackerError> {
+        if width == 0 || height == 0 {
+            return Err(TrackerError::Decode(format!(
+                "Images are empty ({}x{})",
+                width, height
+            )));
+        }
+        let (calib_width, calib_height) = match (self.width, self.height) {
+            (Some(w), Some(h)) if (w, h) != (width, height) => (w, h),
+            _ => {
+                self.width = Some(width);
+                self.height = Some(height);
+                return Ok(false);
+            }
+        };
+        if width * calib_height != height * calib_width {
+            return Err(TrackerError::Config(format!(
+                "Images are {}x{} but calibration expects {}x{}",
+                width, height, calib_width, calib_height
+            )));
+        }
+        let scale = width as f32 / calib_width as f32;
+        self.fx *= scale;
+        self.fy *= scale;
+        self.cx *= scale;
+        self.cy *= scale;
+        self.width = Some(width);
+        self.height = Some(height);
+        Ok(true)
+    }
+
+    /// Camera center and the four image corners back-projected at `depth`,
+    /// in world coordinates for the given camera pose.
+    /// Image size is taken from the calibration, or guessed from the principal point.
+    pub fn frustum(&self, pose: &Iso3, depth: f32) -> [Vector3<f32>; 5] {
+        let width = self.width.map_or(2.0 * self.cx, |w| w as f32);
+        let height = self.height.map_or(2.0 * self.cy, |h| h as f32);
+        let corner = |u: f32, v: f32| {
+            let x = (u - self.cx) / self.fx * depth;
+            let y = (v - self.cy) / self.fy * depth;
+            (pose * Point3::new(x, y, depth)).coords
+        };
+        [
+            pose.translation.vector,
+            corner(0.0, 0.0),
+            corner(width, 0.0),
+            corner(width, height),
+            corner(0.0, height),
+        ]
+    }
+
+    pub fn intrinsics(&self) -> Intrinsics {
+        Intrinsics {
+            principal_point: (self.cx, self.cy),
+            focal: (self.fx, self.fy),
+            skew: 0.0,
+        }
+    }
+}
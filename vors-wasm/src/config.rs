@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::error::TrackerError;
+use visual_odometry_rs::core::camera::Intrinsics;
+use visual_odometry_rs::core::track::inverse_compositional_norm as track;
+
+/// Maximum number of pyramid levels, each one halving the image resolution.
+pub const MAX_NB_LEVELS: usize = 8;
+
+/// Tracking parameters that can be tuned from JavaScript.
+/// Missing fields take their default value.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct TrackerParams {
+    /// Number of levels in the multi-resolution pyramids.
+    pub nb_levels: usize,
+    /// Gradient threshold used to select candidate points.
+    pub candidates_diff_threshold: u16,
+    /// Initial variance of the inverse depth of candidate points.
+    pub idepth_variance: f32,
+}
+
+impl Default for TrackerParams {
+    fn default() -> TrackerParams {
+        TrackerParams {
+            nb_levels: 6,
+            candidates_diff_threshold: 7,
+            idepth_variance: 0.0001,
+        }
+    }
+}
+
+impl TrackerParams {
+    /// Read parameters from a JavaScript object.
+    /// `undefined` or `null` give the default parameters.
+    pub fn from_js(value: JsValue) -> Result<TrackerParams, TrackerError> {
+        if value.is_undefined() || value.is_null() {
+            return Ok(TrackerParams::default());
+        }
+        let params: TrackerParams = serde_wasm_bindgen::from_value(value)
+            .map_err(|e| TrackerError::Config(format!("Invalid tracker config: {}", e)))?;
+        params.validate()?;
+        Ok(params)
+    }
+
+    /// Extract the parameters of an existing tracking configuration.
+    pub fn from_config(config: &track::Config) -> TrackerParams {
+        TrackerParams {
+            nb_levels: config.nb_levels,
+            candidates_diff_threshold: config.candidates_diff_threshold,
+            idepth_variance: config.idepth_variance,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), TrackerError> {
+        // The second level is used to pick and click points.
+        if self.nb_levels < 2 || self.nb_levels > MAX_NB_LEVELS {
+            return Err(TrackerError::Config(format!(
+                "nb_levels must be between 2 and {}",
+                MAX_NB_LEVELS
+            )));
+        }
+        if !self.idepth_variance.is_finite() || self.idepth_variance <= 0.0 {
+            return Err(TrackerError::Config(
+                "idepth_variance must be finite and strictly positive".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Build the full tracking configuration for a given camera.
+    pub fn to_config(&self, intrinsics: Intrinsics, depth_scale: f32) -> track::Config {
+        track::Config {
+            nb_levels: self.nb_levels,
+            candidates_diff_threshold: self.candidates_diff_threshold,
+            depth_scale,
+            intrinsics,
+            idepth_variance: self.idepth_variance,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Calibration;
+use crate::config::TrackerParams;
+use crate::error::TrackerError;
+use crate::pnp::PnpParams;
+use crate::session::ArchiveFingerprint;
+use crate::tracking_result::Pose;
+
+/// Version of the edit log format, increased on incompatible changes.
+pub const EDIT_LOG_VERSION: u32 = 1;
+
+/// Points clicked to compute P3P hypotheses, in the reference keyframe
+/// and in the keyframe to reinitialize.
+/// There are at least 3 pairs, more give a single robust PnP hypothesis.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct P3pRequest {
+    pub base_frame_id: usize,
+    pub last_tracked_frame_id: usize,
+    pub ref_points: Vec<(f32, f32)>,
+    pub key_points: Vec<(f32, f32)>,
+    /// Parameters of the robust PnP used with more than 3 pairs.
+    #[serde(default)]
+    pub pnp: PnpParams,
+}
+
+/// A call mutating the tracked trajectory.
+/// Externally tagged, since sessions store the edits with bincode,
+/// which does not support internally tagged enums.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Edit {
+    Init {
+        calibration: Calibration,
+        params: TrackerParams,
+        max_association_difference: f64,
+    },
+    Track {
+        frame_id: usize,
+        force_keyframe: bool,
+    },
+    ResetAt {
+        base_frame_id: usize,
+        last_tracked_frame_id: usize,
+        keyframe_id: usize,
+        params: Option<TrackerParams>,
+    },
+    ChooseP3pInitial {
+        id: usize,
+        base_frame_id: usize,
+        p3p: P3pRequest,
+    },
+    UpdateKeyframePose {
+        keyframe_id: usize,
+        pose: Pose,
+    },
+}
+
+/// Ordered list of the edits done since the tracker initialization,
+/// enough to replay them on the same archive.
+#[derive(Serialize, Deserialize)]
+pub struct EditLog {
+    pub version: u32,
+    pub archive: ArchiveFingerprint,
+    pub edits: Vec<Edit>,
+}
+
+impl EditLog {
+    pub fn to_json(&self) -> Result<String, TrackerError> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| TrackerError::Decode(format!("Could not encode edit log: {}", e)))
+    }
+
+    pub fn from_json(json: &str) -> Result<EditLog, TrackerError> {
+        let log: EditLog = serde_json::from_str(json)
+            .map_err(|e| TrackerError::Decode(format!("Invalid edit log: {}", e)))?;
+        if log.version != EDIT_LOG_VERSION {
+            return Err(TrackerError::Decode(format!(
+                "Edit log version {} is not supported (expected {})",
+                log.version, EDIT_LOG_VERSION
+            )));
+        }
+        match log.edits.first() {
+            Some(Edit::Init { .. }) => Ok(log),
+            _ => Err(TrackerError::Decode(
+                "Edit log must start with an init edit".to_owned(),
+            )),
+        }
+    }
+}
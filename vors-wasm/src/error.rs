@@ -0,0 +1,103 @@
+use serde::Serialize;
+use std::fmt;
+use wasm_bindgen::JsValue;
+
+/// Errors that can happen while loading an archive or tracking a sequence.
+/// They are converted into `{ code, message }` JavaScript objects
+/// when crossing the wasm boundary, so the app can keep running.
+#[derive(Debug)]
+pub enum TrackerError {
+    /// Missing or unreadable entry in the archive.
+    Archive(String),
+    /// An image or a text file could not be decoded.
+    Decode(String),
+    /// Invalid configuration or camera parameters.
+    Config(String),
+    /// The tracker is not in a state allowing the requested operation.
+    Tracking(String),
+    /// Access to a frame, keyframe or hypothesis that does not exist.
+    IndexOutOfRange {
+        what: &'static str,
+        index: usize,
+        len: usize,
+    },
+}
+
+impl TrackerError {
+    /// Machine-readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TrackerError::Archive(_) => "archive",
+            TrackerError::Decode(_) => "decode",
+            TrackerError::Config(_) => "config",
+            TrackerError::Tracking(_) => "tracking",
+            TrackerError::IndexOutOfRange { .. } => "index_out_of_range",
+        }
+    }
+
+    pub fn not_initialized() -> TrackerError {
+        TrackerError::Tracking("Tracker is not initialized, call init first".to_owned())
+    }
+}
+
+impl fmt::Display for TrackerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrackerError::Archive(msg) => write!(f, "Archive error: {}", msg),
+            TrackerError::Decode(msg) => write!(f, "Decode error: {}", msg),
+            TrackerError::Config(msg) => write!(f, "Config error: {}", msg),
+            TrackerError::Tracking(msg) => write!(f, "Tracking error: {}", msg),
+            TrackerError::IndexOutOfRange { what, index, len } => {
+                write!(f, "Index {} out of range for {} (length {})", index, what, len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TrackerError {}
+
+#[derive(Serialize)]
+struct JsError<'a> {
+    code: &'a str,
+    message: String,
+}
+
+impl From<TrackerError> for JsValue {
+    fn from(err: TrackerError) -> JsValue {
+        let js_error = JsError {
+            code: err.code(),
+            message: err.to_string(),
+        };
+        serde_wasm_bindgen::to_value(&js_error).unwrap_or_else(|_| JsValue::from(err.to_string()))
+    }
+}
+
+/// Checked access into a slice, reporting which collection was indexed.
+pub fn get_checked<'a, T>(
+    slice: &'a [T],
+    index: usize,
+    what: &'static str,
+) -> Result<&'a T, TrackerError> {
+    slice.get(index).ok_or(TrackerError::IndexOutOfRange {
+        what,
+        index,
+        len: slice.len(),
+    })
+}
+
+/// Checked mutable access into a slice, reporting which collection was indexed.
+pub fn get_checked_mut<'a, T>(
+    slice: &'a mut [T],
+    index: usize,
+    what: &'static str,
+) -> Result<&'a mut T, TrackerError> {
+    let len = slice.len();
+    slice
+        .get_mut(index)
+        .ok_or(TrackerError::IndexOutOfRange { what, index, len })
+}
+
+/// Convert a Rust value into a JavaScript value.
+pub fn to_js_value<T: Serialize>(value: &T) -> Result<JsValue, TrackerError> {
+    serde_wasm_bindgen::to_value(value).map_err(|e| TrackerError::Decode(e.to_string()))
+}
@@ -0,0 +1,260 @@
+use nalgebra::{Matrix3, Rotation3, Translation3, UnitQuaternion, Vector3};
+use serde::Serialize;
+
+use crate::associate::DEFAULT_MAX_DIFFERENCE;
+use crate::error::TrackerError;
+use visual_odometry_rs::misc::type_aliases::{Float, Iso3};
+
+/// Parse a TUM groundtruth file, with lines "timestamp tx ty tz qx qy qz qw".
+pub fn parse_groundtruth(content: &str) -> Result<Vec<(f64, Iso3)>, TrackerError> {
+    let mut poses = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || TrackerError::Decode(format!("Invalid groundtruth line: {}", line));
+        let values: Vec<f64> = line
+            .split_whitespace()
+            .map(|w| w.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        if values.len() != 8 || values.iter().any(|v| !v.is_finite()) {
+            return Err(invalid());
+        }
+        let v: Vec<Float> = values[1..].iter().map(|&x| x as Float).collect();
+        let translation = Translation3::new(v[0], v[1], v[2]);
+        let rotation =
+            UnitQuaternion::from_quaternion(nalgebra::Quaternion::new(v[6], v[3], v[4], v[5]));
+        poses.push((values[0], Iso3::from_parts(translation, rotation)));
+    }
+    poses.sort_by(|(t1, _), (t2, _)| t1.total_cmp(t2));
+    Ok(poses)
+}
+
+/// Index of the groundtruth pose closest in time, if close enough,
+/// with the same tolerance as the association of color and depth images.
+/// Groundtruth must be sorted by timestamp.
+pub fn closest_groundtruth(groundtruth: &[(f64, Iso3)], timestamp: f64) -> Option<usize> {
+    let k = match groundtruth.binary_search_by(|(t, _)| t.total_cmp(&timestamp)) {
+        Ok(k) | Err(k) => k,
+    };
+    let before = k.checked_sub(1);
+    let after = if k < groundtruth.len() { Some(k) } else { None };
+    let diff = |i: usize| (groundtruth[i].0 - timestamp).abs();
+    let closest = match (before, after) {
+        (Some(b), Some(a)) => {
+            if diff(b) <= diff(a) {
+                b
+            } else {
+                a
+            }
+        }
+        (Some(b), None) => b,
+        (None, Some(a)) => a,
+        (None, None) => return None,
+    };
+    if diff(closest) < DEFAULT_MAX_DIFFERENCE {
+        Some(closest)
+    } else {
+        None
+    }
+}
+
+/// Rigid transformation minimizing the squared distances
+/// between `model` points transformed and `data` points (Horn / Umeyama, without scale).
+pub fn align(model: &[Vector3<Float>], data: &[Vector3<Float>]) -> Iso3 {
+    let n = model.len() as Float;
+    let mean =
+        |points: &[Vector3<Float>]| points.iter().fold(Vector3::zeros(), |acc, p| acc + p) / n;
+    let mu_model = mean(model);
+    let mu_data = mean(data);
+    let covariance = model
+        .iter()
+        .zip(data.iter())
+        .fold(Matrix3::zeros(), |acc, (m, d)| {
+            acc + (d - mu_data) * (m - mu_model).transpose()
+        })
+        / n;
+    let svd = covariance.svd(true, true);
+    let u = svd.u.unwrap();
+    let v_t = svd.v_t.unwrap();
+    let mut s = Matrix3::identity();
+    if u.determinant() * v_t.determinant() < 0.0 {
+        s[(2, 2)] = -1.0;
+    }
+    let rotation = Rotation3::from_matrix_unchecked(u * s * v_t);
+    let translation = mu_data - rotation * mu_model;
+    Iso3::from_parts(
+        Translation3::from(translation),
+        UnitQuaternion::from_rotation_matrix(&rotation),
+    )
+}
+
+/// Estimated trajectory matched with groundtruth and aligned to it.
+pub struct AlignedTrajectory {
+    /// Frame ids having a groundtruth pose.
+    pub frame_ids: Vec<usize>,
+    /// Estimated poses of those frames.
+    pub estimated: Vec<Iso3>,
+    /// Groundtruth poses of those frames.
+    pub groundtruth: Vec<Iso3>,
+    /// Transformation from the estimated frame to the groundtruth frame.
+    pub alignment: Iso3,
+}
+
+impl AlignedTrajectory {
+    /// Match estimated poses (indexed by frame id) to groundtruth by timestamp,
+    /// and align them by least squares on positions.
+    pub fn new(
+        poses: &[Iso3],
+        timestamps: &[f64],
+        groundtruth: &[(f64, Iso3)],
+    ) -> Result<AlignedTrajectory, TrackerError> {
+        let mut frame_ids = Vec::new();
+        let mut estimated = Vec::new();
+        let mut gt = Vec::new();
+        for (frame_id, (pose, &timestamp)) in poses.iter().zip(timestamps.iter()).enumerate() {
+            if let Some(k) = closest_groundtruth(groundtruth, timestamp) {
+                frame_ids.push(frame_id);
+                estimated.push(*pose);
+                gt.push(groundtruth[k].1);
+            }
+        }
+        if frame_ids.len() < 3 {
+            return Err(TrackerError::Tracking(format!(
+                "Only {} tracked frames have a groundtruth pose, at least 3 are needed",
+                frame_ids.len()
+            )));
+        }
+        let model: Vec<_> = estimated.iter().map(|p| p.translation.vector).collect();
+        let data: Vec<_> = gt.iter().map(|p| p.translation.vector).collect();
+        let alignment = align(&model, &data);
+        Ok(AlignedTrajectory {
+            frame_ids,
+            estimated,
+            groundtruth: gt,
+            alignment,
+        })
+    }
+
+    /// Transformation from the estimated frame to the groundtruth frame
+    /// making the first matched poses coincide.
+    pub fn first_pose_alignment(&self) -> Iso3 {
+        self.groundtruth[0] * self.estimated[0].inverse()
+    }
+
+    /// Absolute trajectory error of each matched frame, in meters.
+    pub fn absolute_errors(&self) -> Vec<Float> {
+        self.estimated
+            .iter()
+            .zip(self.groundtruth.iter())
+            .map(|(est, gt)| {
+                let aligned = self.alignment * est;
+                (aligned.translation.vector - gt.translation.vector).norm()
+            })
+            .collect()
+    }
+
+    /// Relative pose error between consecutive matched frames,
+    /// as (translation error in meters, rotation error in degrees).
+    pub fn relative_errors(&self) -> Vec<(Float, Float)> {
+        let est = &self.estimated;
+        let gt = &self.groundtruth;
+        (1..est.len())
+            .map(|i| {
+                let est_motion = est[i - 1].inverse() * est[i];
+                let gt_motion = gt[i - 1].inverse() * gt[i];
+                let error = gt_motion.inverse() * est_motion;
+                (
+                    error.translation.vector.norm(),
+                    error.rotation.angle().to_degrees(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Summary statistics of a list of errors.
+#[derive(Serialize)]
+pub struct ErrorStats {
+    pub rmse: Float,
+    pub mean: Float,
+    pub median: Float,
+    pub max: Float,
+}
+
+impl ErrorStats {
+    pub fn new(errors: &[Float]) -> ErrorStats {
+        if errors.is_empty() {
+            return ErrorStats {
+                rmse: 0.0,
+                mean: 0.0,
+                median: 0.0,
+                max: 0.0,
+            };
+        }
+        let n = errors.len() as Float;
+        let mut sorted = errors.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        ErrorStats {
+            rmse: (errors.iter().map(|e| e * e).sum::<Float>() / n).sqrt(),
+            mean: errors.iter().sum::<Float>() / n,
+            median: sorted[sorted.len() / 2],
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FrameError {
+    pub frame_id: usize,
+    pub error: Float,
+}
+
+#[derive(Serialize)]
+pub struct RelativeFrameError {
+    pub frame_id: usize,
+    pub translation: Float,
+    pub rotation: Float,
+}
+
+/// Evaluation of the estimated trajectory against groundtruth, sent to JavaScript.
+#[derive(Serialize)]
+pub struct Evaluation {
+    pub nb_matched_frames: usize,
+    pub ate: ErrorStats,
+    pub ate_frames: Vec<FrameError>,
+    pub rpe_translation: ErrorStats,
+    pub rpe_rotation: ErrorStats,
+    pub rpe_frames: Vec<RelativeFrameError>,
+}
+
+impl Evaluation {
+    pub fn new(trajectory: &AlignedTrajectory) -> Evaluation {
+        let ate_errors = trajectory.absolute_errors();
+        let rpe_errors = trajectory.relative_errors();
+        let rpe_translations: Vec<_> = rpe_errors.iter().map(|&(t, _)| t).collect();
+        let rpe_rotations: Vec<_> = rpe_errors.iter().map(|&(_, r)| r).collect();
+        Evaluation {
+            nb_matched_frames: trajectory.frame_ids.len(),
+            ate: ErrorStats::new(&ate_errors),
+            ate_frames: trajectory
+                .frame_ids
+                .iter()
+                .zip(ate_errors.iter())
+                .map(|(&frame_id, &error)| FrameError { frame_id, error })
+                .collect(),
+            rpe_translation: ErrorStats::new(&rpe_translations),
+            rpe_rotation: ErrorStats::new(&rpe_rotations),
+            rpe_frames: trajectory.frame_ids[1..]
+                .iter()
+                .zip(rpe_errors.iter())
+                .map(|(&frame_id, &(translation, rotation))| RelativeFrameError {
+                    frame_id,
+                    translation,
+                    rotation,
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use nalgebra::Vector3;
+use std::fmt::Write;
+
+/// Write colored points in the PLY format, ascii or binary little endian.
+/// `points` and `normals` have 3 floats per point, `colors` 3 bytes (RGB) per point.
+/// Triangles in `faces` index the points, none are written if it is empty.
+pub fn write_ply(
+    points: &[f32],
+    colors: &[u8],
+    normals: Option<&[f32]>,
+    faces: &[[u32; 3]],
+    binary: bool,
+) -> Vec<u8> {
+    let nb_points = points.len() / 3;
+    let format = if binary {
+        "binary_little_endian"
+    } else {
+        "ascii"
+    };
+    let mut header = String::new();
+    writeln!(header, "ply").unwrap();
+    writeln!(header, "format {} 1.0", format).unwrap();
+    writeln!(header, "element vertex {}", nb_points).unwrap();
+    header.push_str("property float x\nproperty float y\nproperty float z\n");
+    if normals.is_some() {
+        header.push_str("property float nx\nproperty float ny\nproperty float nz\n");
+    }
+    header.push_str("property uchar red\nproperty uchar green\nproperty uchar blue\n");
+    if !faces.is_empty() {
+        writeln!(header, "element face {}", faces.len()).unwrap();
+        header.push_str("property list uchar int vertex_indices\n");
+    }
+    header.push_str("end_header\n");
+
+    let mut buffer = header.into_bytes();
+    for i in 0..nb_points {
+        let point = &points[3 * i..3 * i + 3];
+        let normal = normals.map(|n| &n[3 * i..3 * i + 3]);
+        let color = &colors[3 * i..3 * i + 3];
+        if binary {
+            for x in point.iter().chain(normal.unwrap_or(&[]).iter()) {
+                buffer.extend_from_slice(&x.to_le_bytes());
+            }
+            buffer.extend_from_slice(color);
+        } else {
+            let mut line = format!("{} {} {}", point[0], point[1], point[2]);
+            if let Some(n) = normal {
+                write!(line, " {} {} {}", n[0], n[1], n[2]).unwrap();
+            }
+            writeln!(line, " {} {} {}", color[0], color[1], color[2]).unwrap();
+            buffer.extend_from_slice(line.as_bytes());
+        }
+    }
+    for face in faces {
+        if binary {
+            buffer.push(3);
+            for &v in face {
+                buffer.extend_from_slice(&(v as i32).to_le_bytes());
+            }
+        } else {
+            let line = format!("3 {} {} {}\n", face[0], face[1], face[2]);
+            buffer.extend_from_slice(line.as_bytes());
+        }
+    }
+    buffer
+}
+
+/// Write colored points, triangles and camera frustums in the OBJ format.
+/// Colors are written after vertex coordinates, as read by MeshLab and Blender.
+/// Triangles in `faces` index the points (from 0).
+/// Each frustum is its camera center followed by the four corners of its image plane,
+/// and is drawn with lines.
+pub fn write_obj(
+    points: &[f32],
+    colors: &[u8],
+    faces: &[[u32; 3]],
+    frustums: &[[Vector3<f32>; 5]],
+) -> String {
+    let mut obj = String::new();
+    writeln!(
+        obj,
+        "# Point cloud and keyframe cameras exported by wasm-vors"
+    )
+    .unwrap();
+    writeln!(
+        obj,
+        "o {}",
+        if faces.is_empty() {
+            "point_cloud"
+        } else {
+            "mesh"
+        }
+    )
+    .unwrap();
+    for (p, c) in points.chunks(3).zip(colors.chunks(3)) {
+        let to_float = |x: u8| f32::from(x) / 255.0;
+        writeln!(
+            obj,
+            "v {} {} {} {} {} {}",
+            p[0],
+            p[1],
+            p[2],
+            to_float(c[0]),
+            to_float(c[1]),
+            to_float(c[2])
+        )
+        .unwrap();
+    }
+
+    // OBJ indices start at 1.
+    for face in faces {
+        writeln!(obj, "f {} {} {}", face[0] + 1, face[1] + 1, face[2] + 1).unwrap();
+    }
+    let mut base = points.len() / 3 + 1;
+    for (i, frustum) in frustums.iter().enumerate() {
+        writeln!(obj, "o keyframe_{}", i).unwrap();
+        for v in frustum.iter() {
+            writeln!(obj, "v {} {} {}", v.x, v.y, v.z).unwrap();
+        }
+        for corner in 1..5 {
+            writeln!(obj, "l {} {}", base, base + corner).unwrap();
+        }
+        writeln!(
+            obj,
+            "l {} {} {} {} {}",
+            base + 1,
+            base + 2,
+            base + 3,
+            base + 4,
+            base + 1
+        )
+        .unwrap();
+        base += 5;
+    }
+    obj
+}
@@ -2,14 +2,43 @@ use serde_wasm_bindgen;
 use wasm_bindgen::prelude::*;
 
 use image;
-use nalgebra::{self, DMatrix, Quaternion, Translation, UnitQuaternion, Vector3, Vector4};
+use nalgebra::{self, DMatrix, Point3, Quaternion, Translation, UnitQuaternion, Vector3, Vector4};
 use std::{error::Error, io::Read};
 
+mod archive;
+mod associate;
+mod camera;
+mod config;
+mod edit_log;
+mod error;
+mod evaluation;
+mod export;
+mod pnp;
+mod session;
+mod tracking_result;
+mod tsdf;
+use archive::{
+    entry_name, get_buffer, inflate_tar_entries, tar_entries, zip_entries, ArchiveFormat, FileEntry,
+};
+use camera::{Calibration, CALIBRATION_FILES};
+use config::TrackerParams;
+use edit_log::{Edit, EditLog, P3pRequest, EDIT_LOG_VERSION};
+use error::{get_checked, get_checked_mut, to_js_value, TrackerError};
+use evaluation::{AlignedTrajectory, Evaluation};
+use pnp::PnpParams;
+use session::{
+    ArchiveFingerprint, CameraPathState, Merge, PointCloudState, Session, TrackerState,
+    SESSION_VERSION,
+};
+use tracking_result::{
+    BranchInfo, P3pReset, P3pVisualization, PnpResult, Pose, TrackingResult, TrackingStats,
+};
+use tsdf::{voxel_index, TsdfParams, TsdfVolume};
+
 use byteorder::{BigEndian, ReadBytesExt};
 use png::HasParameters;
 use std::collections::HashMap;
 use std::io::Cursor;
-use tar;
 
 use visual_odometry_rs as vors;
 use vors::core::camera::Intrinsics;
@@ -24,30 +53,55 @@ use p3p;
 
 type Vec3 = Vector3<f32>;
 type Vec4 = Vector4<f32>;
+/// Pixel coordinates of a point clicked in a keyframe.
+type Pixel = (f32, f32);
 
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
 }
 
+#[cfg(target_arch = "wasm32")]
 macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
+// Outside of the browser, in unit tests, log to stderr.
+#[cfg(not(target_arch = "wasm32"))]
+macro_rules! console_log {
+    ($($t:tt)*) => (eprintln!($($t)*))
+}
+
 #[wasm_bindgen]
 pub struct WasmTracker {
     tar_buffer: Vec<u8>,
     entries: HashMap<String, FileEntry>,
     associations: Vec<tum_rgbd::Association>,
+    /// Maximum time difference (in seconds) between color and depth images
+    /// when associations are built from rgb.txt and depth.txt.
+    pub max_association_difference: f64,
+    /// Number of color or depth images left without association.
+    pub nb_dropped_frames: usize,
     tracker: Option<track::Tracker>,
+    calibration: Option<Calibration>,
     pub change_keyframe: bool,
     keyframes: Vec<DMatrix<u8>>,
     keyframes_candidates: Vec<Vec<(usize, usize)>>,
+    /// Frame id of each keyframe.
+    keyframes_frame_ids: Vec<usize>,
+    /// Frame id of the keyframe the tracker currently tracks against.
+    tracker_keyframe_id: usize,
     current_keyframe_data: Vec<u8>,
     reference_keyframe_data: Vec<u8>,
     poses_history: Vec<Iso3>,
     p3p_poses: Vec<Iso3>,
+    /// Clicked points of the last P3P hypotheses, recorded when one is chosen.
+    p3p_request: Option<P3pRequest>,
+    groundtruth: Vec<(f64, Iso3)>,
+    /// Mutating calls since `init`, saved in sessions and restored by `load_session`.
+    edits: Vec<Edit>,
 }
 
 /// Public methods, exported to JavaScript.
@@ -59,14 +113,22 @@ impl WasmTracker {
             tar_buffer: Vec::new(),
             entries: HashMap::new(),
             associations: Vec::new(),
+            max_association_difference: associate::DEFAULT_MAX_DIFFERENCE,
+            nb_dropped_frames: 0,
             tracker: None,
+            calibration: None,
             change_keyframe: false,
             keyframes: vec![],
             keyframes_candidates: vec![],
-            current_keyframe_data: vec![0; 320 * 240 * 4],
-            reference_keyframe_data: vec![0; 320 * 240 * 4],
+            keyframes_frame_ids: vec![],
+            tracker_keyframe_id: 0,
+            current_keyframe_data: vec![],
+            reference_keyframe_data: vec![],
             poses_history: vec![],
             p3p_poses: vec![],
+            p3p_request: None,
+            groundtruth: vec![],
+            edits: vec![],
         }
     }
 
@@ -86,331 +148,634 @@ impl WasmTracker {
         self.current_keyframe_data.as_ptr()
     }
 
-    pub fn build_entries_map(&mut self) {
-        // Init archive from in memory tar buffer.
-        let mut archive = tar::Archive::new(self.tar_buffer.as_slice());
+    /// Width of the keyframe images in the keyframe data buffers.
+    pub fn keyframe_width(&self) -> usize {
+        // Keyframes are transposed.
+        self.keyframes.first().map_or(0, |kf| kf.nrows())
+    }
 
-        for file in archive.entries().expect("48") {
-            // Check for an I/O error.
-            let file = file.expect("50");
-            self.entries.insert(
-                file.path().unwrap().to_str().expect("52").to_owned(),
-                FileEntry {
-                    offset: file.raw_file_position() as usize,
-                    length: file.header().entry_size().expect("55") as usize,
-                },
-            );
+    /// Height of the keyframe images in the keyframe data buffers.
+    pub fn keyframe_height(&self) -> usize {
+        self.keyframes.first().map_or(0, |kf| kf.ncols())
+    }
+
+    /// Number of keyframes since the start of the sequence.
+    pub fn nb_keyframes(&self) -> usize {
+        self.keyframes.len()
+    }
+
+    /// Index the files of the archive.
+    /// The format (tar, tar.gz, tar.zst or zip) is detected from its magic bytes.
+    /// Gzip and zstd compressed tar archives are decompressed in a single pass
+    /// and the compressed buffer is replaced by the content of the files.
+    /// Compressed zip entries are decompressed at the end of the buffer.
+    pub fn build_entries_map(&mut self) -> Result<(), JsValue> {
+        match ArchiveFormat::detect(&self.tar_buffer) {
+            ArchiveFormat::Tar => {
+                self.entries = tar_entries(&self.tar_buffer)?;
+            }
+            ArchiveFormat::Zip => {
+                let (inflated, entries) = zip_entries(&self.tar_buffer)?;
+                self.tar_buffer.extend_from_slice(&inflated);
+                self.entries = entries;
+            }
+            format => {
+                console_log!("Decompressing {:?} archive ...", format);
+                let (content, entries) = inflate_tar_entries(&self.tar_buffer, format)?;
+                self.tar_buffer = content;
+                self.entries = entries;
+            }
+        }
+        Ok(())
+    }
+
+    /// Initialize the tracker with the first frame of the archive.
+    /// The camera calibration is read from a calibration file in the archive if any,
+    /// otherwise from `camera`, either a preset name or an intrinsics object.
+    /// `config` is an optional object of tracker parameters.
+    pub fn init(&mut self, camera: JsValue, config: JsValue) -> Result<usize, JsValue> {
+        let calibration = match self.archive_calibration()? {
+            Some(calibration) => calibration,
+            None => Calibration::from_js(camera)?,
+        };
+        let params = TrackerParams::from_js(config)?;
+        let (associations, nb_dropped) = self.load_associations(self.max_association_difference)?;
+        self.associations = associations;
+        self.nb_dropped_frames = nb_dropped;
+        if self.associations.is_empty() {
+            return Err(TrackerError::Decode("Archive contains no frame".to_owned()).into());
+        }
+        self.groundtruth = self.load_groundtruth()?;
+        Ok(self.init_with(calibration, params)?)
+    }
+
+    pub fn pick_reference_kf_data(&mut self, index: usize) -> Result<(), JsValue> {
+        let keyframe = get_checked(&self.keyframes, index, "keyframes")?;
+        update_kf_data(&mut self.reference_keyframe_data, keyframe);
+        let (width, _) = keyframe.shape(); // DMatrix is transposed
+        let data = &mut self.reference_keyframe_data[..];
+        for &(x, y) in get_checked(&self.keyframes_candidates, index, "keyframes")?.iter() {
+            let pix = 4 * (y * width + x);
+            data[pix] = 255;
+            data[pix + 1] = 0;
+            data[pix + 2] = 0;
         }
+        Ok(())
     }
 
-    pub fn init(&mut self, camera_id: &str) -> usize {
-        let intrinsics = create_camera(camera_id).expect("62");
-        let associations_buffer = get_buffer("associations.txt", &self.tar_buffer, &self.entries);
-        self.associations = parse_associations_buf(associations_buffer).expect("64");
+    pub fn pick_current_kf_data(&mut self, index: usize) -> Result<(), JsValue> {
+        let keyframe = get_checked(&self.keyframes, index, "keyframes")?;
+        update_kf_data(&mut self.current_keyframe_data, keyframe);
+        Ok(())
+    }
 
-        // Setup tracking configuration.
-        let config = track::Config {
-            nb_levels: 6,
-            candidates_diff_threshold: 7,
-            depth_scale: tum_rgbd::DEPTH_SCALE,
-            intrinsics: intrinsics,
-            idepth_variance: 0.0001,
+    /// Whether the archive contains a groundtruth.txt file.
+    pub fn has_groundtruth(&self) -> bool {
+        !self.groundtruth.is_empty()
+    }
+
+    /// Evaluate the tracked trajectory against groundtruth.
+    /// The trajectory is aligned to groundtruth by least squares on positions,
+    /// then absolute trajectory error (ATE) and relative pose error (RPE)
+    /// are computed per frame and overall.
+    pub fn evaluate(&self) -> Result<JsValue, JsValue> {
+        let evaluation = Evaluation::new(&self.aligned_trajectory()?);
+        Ok(to_js_value(&evaluation)?)
+    }
+
+    /// Camera calibration used by the tracker.
+    pub fn calibration(&self) -> Result<JsValue, JsValue> {
+        let calibration = self
+            .calibration
+            .as_ref()
+            .ok_or_else(TrackerError::not_initialized)?;
+        Ok(to_js_value(calibration)?)
+    }
+
+    /// Current tracker parameters.
+    pub fn config(&self) -> Result<JsValue, JsValue> {
+        let params = TrackerParams::from_config(self.tracker()?.config());
+        Ok(to_js_value(&params)?)
+    }
+
+    /// Restart tracking from a previous frame.
+    /// `config` is an optional object of tracker parameters to use from now on,
+    /// the current ones are kept if it is `undefined`.
+    pub fn reset_at(
+        &mut self,
+        base_frame_id: usize,
+        last_tracked_frame_id: usize,
+        keyframe_id: usize,
+        config_js: JsValue,
+    ) -> Result<(), JsValue> {
+        let params = if config_js.is_undefined() || config_js.is_null() {
+            None
+        } else {
+            Some(TrackerParams::from_js(config_js)?)
+        };
+        self.reset_tracker(
+            base_frame_id,
+            last_tracked_frame_id,
+            keyframe_id,
+            params.as_ref(),
+        )?;
+        self.edits.push(Edit::ResetAt {
+            base_frame_id,
+            last_tracked_frame_id,
+            keyframe_id,
+            params,
+        });
+        Ok(())
+    }
+
+    /// Restart tracking from `base_frame_id` in one call, with the pose of the last
+    /// tracked frame computed by P3P from three points clicked in the reference keyframe
+    /// (`p3p_ref_points`) and in the last keyframe (`p3p_key_points`),
+    /// or by robust PnP with more points, tuned by the optional `config` of `solve_pnp`.
+    /// The hypothesis with the highest probability is chosen, unless `hypothesis` is a number,
+    /// with the same ids as the probabilities of `p3p_visualize` (0 keeps the current pose).
+    /// `last_tracked_frame_id` must be the frame of the keyframe being reinitialized,
+    /// tracked frames after it are dropped.
+    /// Return `{ keyframe_id, hypothesis, probabilities, rejected }`
+    /// with the ids of the pairs rejected by robust PnP.
+    pub fn reset_at_p3p(
+        &mut self,
+        base_frame_id: usize,
+        last_tracked_frame_id: usize,
+        p3p_ref_points: JsValue,
+        p3p_key_points: JsValue,
+        hypothesis: JsValue,
+        config: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let (ref_points, key_points) = clicked_points(p3p_ref_points, p3p_key_points)?;
+        let request = P3pRequest {
+            base_frame_id,
+            last_tracked_frame_id,
+            ref_points,
+            key_points,
+            pnp: PnpParams::from_js(config)?,
+        };
+        let reset = self.reset_p3p(request, forced_hypothesis(hypothesis)?)?;
+        Ok(to_js_value(&reset)?)
+    }
+
+    /// First half of `reset_at_p3p`, with the same arguments and result:
+    /// compute and check the hypotheses, and keep them for `choose_p3p_initial`.
+    /// The tracker is not modified, so a caller can save its state in between.
+    pub fn prepare_p3p(
+        &mut self,
+        base_frame_id: usize,
+        last_tracked_frame_id: usize,
+        p3p_ref_points: JsValue,
+        p3p_key_points: JsValue,
+        hypothesis: JsValue,
+        config: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let (ref_points, key_points) = clicked_points(p3p_ref_points, p3p_key_points)?;
+        let request = P3pRequest {
+            base_frame_id,
+            last_tracked_frame_id,
+            ref_points,
+            key_points,
+            pnp: PnpParams::from_js(config)?,
+        };
+        let prepared = self.prepare_p3p_reset(request, forced_hypothesis(hypothesis)?)?;
+        Ok(to_js_value(&prepared)?)
+    }
+
+    /// Estimate the pose of the last keyframe from N >= 3 pairs of points clicked
+    /// in the reference keyframe (`p3p_ref_points`) and in the last keyframe (`p3p_key_points`),
+    /// robust to mis-clicks: RANSAC over P3P minimal sets, then refinement on inliers.
+    /// `config` is an optional object `{ max_iterations, inlier_threshold, refine_iterations }`.
+    /// The tracker is not modified.
+    /// Return `{ pose, inliers, rejected, errors }` with the ids of the inlier and
+    /// rejected pairs, and the reprojection error (in pixels) of each pair.
+    pub fn solve_pnp(
+        &self,
+        base_frame_id: usize,
+        p3p_ref_points: JsValue,
+        p3p_key_points: JsValue,
+        config: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let (ref_points, key_points) = clicked_points(p3p_ref_points, p3p_key_points)?;
+        let params = PnpParams::from_js(config)?;
+        let tracker_config = self.tracker()?.config().clone();
+        let (depth_map, img) = self.read_frame(base_frame_id)?;
+        let assoc = &self.associations[base_frame_id];
+        let base_tracker = tracker_config.init(
+            assoc.depth_timestamp,
+            &depth_map,
+            assoc.color_timestamp,
+            img,
+        );
+        let base_pose = *get_checked(&self.poses_history, base_frame_id, "poses_history")?;
+        let world_points = clicked_world_points(&base_tracker, &base_pose, &ref_points)?;
+        let intrinsics = &base_tracker.intrinsics()[1];
+        let solution = pnp::solve(&world_points, &key_points, intrinsics, &params)?;
+        let (inliers, rejected): (Vec<usize>, Vec<usize>) =
+            (0..key_points.len()).partition(|&i| solution.inliers[i]);
+        let result = PnpResult {
+            pose: Pose::from(&solution.pose),
+            inliers,
+            rejected,
+            errors: solution.errors,
+        };
+        Ok(to_js_value(&result)?)
+    }
+
+    /// Compute the P3P hypotheses of points clicked as in `reset_at_p3p`,
+    /// with the optional robust PnP `config`, and push the points of the keyframe
+    /// seen from each of them in the point cloud, after the last keyframe.
+    /// Hypotheses are kept for `choose_p3p_initial`.
+    /// Return `{ probabilities, rejected }`, the probability of each hypothesis,
+    /// the current pose coming first, and the ids of the pairs rejected by robust PnP.
+    pub fn p3p_visualize(
+        &mut self,
+        base_frame_id: usize,
+        last_tracked_frame_id: usize,
+        p3p_ref_points: JsValue,
+        p3p_key_points: JsValue,
+        config: JsValue,
+        point_cloud: &mut PointCloud,
+    ) -> Result<JsValue, JsValue> {
+        let (ref_points, key_points) = clicked_points(p3p_ref_points, p3p_key_points)?;
+        let request = P3pRequest {
+            base_frame_id,
+            last_tracked_frame_id,
+            ref_points,
+            key_points,
+            pnp: PnpParams::from_js(config)?,
+        };
+        let (_, hypotheses) = self.store_p3p_hypotheses(request, None)?;
+
+        // Update the 3D points buffer with 3D points for each pose,
+        // except the first one which is the current pose.
+        let tracker = self.tracker()?;
+        hypotheses.poses.iter().skip(1).for_each(|&p3p_pose| {
+            console_log!("{:?}", p3p_pose.translation);
+            let mut temp_tracker = tracker.clone();
+            temp_tracker.reset_pose(p3p_pose, p3p_pose);
+            point_cloud.push_keyframe(&temp_tracker, false);
+        });
+        let visualization = P3pVisualization {
+            probabilities: hypotheses.probabilities,
+            rejected: hypotheses.rejected,
+        };
+        Ok(to_js_value(&visualization)?)
+    }
+
+    pub fn choose_p3p_initial(
+        &mut self,
+        id: usize,
+        base_frame_id: usize,
+    ) -> Result<usize, JsValue> {
+        Ok(self.choose_p3p(id, base_frame_id)?)
+    }
+
+    pub fn track(&mut self, frame_id: usize, force_keyframe: bool) -> Result<JsValue, JsValue> {
+        let result = self.track_frame(frame_id, force_keyframe)?;
+        Ok(to_js_value(&result)?)
+    }
+
+    /// Correct the pose of a keyframe, `{ translation, rotation }`
+    /// with the rotation quaternion in (x, y, z, w) order,
+    /// in the tracked trajectory, the camera path and the point cloud.
+    /// Frames tracked from the keyframe, up to the next one, get the same correction.
+    /// Points of the keyframe section, and those merged into it, move with it.
+    /// Return the updated section, whose points must be transfered again to the GPU.
+    pub fn update_keyframe_pose(
+        &mut self,
+        kf_id: usize,
+        pose: JsValue,
+        camera_path: &mut CameraPath,
+        point_cloud: &mut PointCloud,
+    ) -> Result<Section, JsValue> {
+        let pose = Pose::from_js(pose)?.to_iso3();
+        // Check the keyframe everywhere before moving anything.
+        let index = *get_checked(&camera_path.indices_kf, kf_id, "indices_kf")?;
+        get_checked(&camera_path.frames, index / 3, "frames")?;
+        get_checked(&point_cloud.sections, kf_id, "sections")?;
+        self.set_keyframe_pose(kf_id, pose)?;
+        camera_path.set_keyframe_pose(kf_id, pose)?;
+        Ok(point_cloud.update_keyframe_pose(kf_id, pose)?)
+    }
+
+    /// Edit log of the mutating calls since `init`, as JSON.
+    /// Sessions keep the edit log, so it is still available after `load_session`.
+    pub fn edit_log(&self) -> Result<String, JsValue> {
+        if self.edits.is_empty() {
+            return Err(TrackerError::Tracking(
+                "No edit log, the tracker was not initialized with init".to_owned(),
+            )
+            .into());
+        }
+        let log = EditLog {
+            version: EDIT_LOG_VERSION,
+            archive: self.fingerprint(),
+            edits: self.edits.clone(),
+        };
+        Ok(log.to_json()?)
+    }
+
+    /// Replay an edit log on the same archive, instead of calling `init`.
+    /// The archive must already be indexed with `build_entries_map`.
+    /// Tracking is deterministic, so this reproduces the trajectory of the log,
+    /// which can then be exported with `export_tum_trajectory`.
+    /// Return the number of frames contained in the archive.
+    pub fn replay(&mut self, log: &str) -> Result<usize, JsValue> {
+        let log = EditLog::from_json(log)?;
+        let mut edits = log.edits.into_iter();
+        if let Some(Edit::Init {
+            calibration,
+            params,
+            max_association_difference,
+        }) = edits.next()
+        {
+            let (associations, nb_dropped) = self.load_associations(max_association_difference)?;
+            let groundtruth = self.load_groundtruth()?;
+            let fingerprint = ArchiveFingerprint::new(&associations);
+            if fingerprint != log.archive {
+                return Err(TrackerError::Archive(format!(
+                    "Edit log was recorded on another archive: {:?}, this one is {:?}",
+                    log.archive, fingerprint
+                ))
+                .into());
+            }
+
+            // The log matches the archive, replace the current state.
+            self.groundtruth = groundtruth;
+            self.max_association_difference = max_association_difference;
+            self.associations = associations;
+            self.nb_dropped_frames = nb_dropped;
+            self.init_with(calibration, params)?;
+        }
+        for edit in edits {
+            self.apply(edit)?;
+        }
+        Ok(self.associations.len())
+    }
+
+    /// Export the tracked trajectory in the TUM format,
+    /// with the color timestamp of each frame.
+    /// Each line is "timestamp tx ty tz qx qy qz qw".
+    pub fn export_tum_trajectory(&self) -> String {
+        tum_trajectory(
+            self.poses_history
+                .iter()
+                .zip(self.associations.iter())
+                .map(|(&pose, assoc)| (assoc.color_timestamp, pose)),
+        )
+    }
+
+    /// Save the interactive work on the sequence (tracked poses, keyframes, resets,
+    /// camera path and point cloud) in a session file, binary or JSON.
+    pub fn save_session(
+        &self,
+        camera_path: &CameraPath,
+        point_cloud: &PointCloud,
+        binary: bool,
+    ) -> Result<Vec<u8>, JsValue> {
+        let session = Session {
+            version: SESSION_VERSION,
+            archive: self.fingerprint(),
+            tracker: self.state()?,
+            camera_path: camera_path.state(),
+            point_cloud: point_cloud.state(),
         };
+        Ok(session.encode(binary)?)
+    }
+
+    /// Restore a session saved on the same archive, instead of calling `init`.
+    /// The archive must already be indexed with `build_entries_map`.
+    /// Keyframe images and the tracker are rebuilt from the frames of the archive.
+    /// Buffers of the camera path and point cloud are reallocated.
+    /// Return the number of frames contained in the archive.
+    pub fn load_session(
+        &mut self,
+        bytes: &[u8],
+        camera_path: &mut CameraPath,
+        point_cloud: &mut PointCloud,
+    ) -> Result<usize, JsValue> {
+        let session = Session::decode(bytes)?;
+        session.check()?;
+        let max_association_difference = session.tracker.max_association_difference;
+        let (associations, nb_dropped) = self.load_associations(max_association_difference)?;
+        let groundtruth = self.load_groundtruth()?;
+        let fingerprint = ArchiveFingerprint::new(&associations);
+        if fingerprint != session.archive {
+            return Err(TrackerError::Archive(format!(
+                "Session was saved on another archive: {:?}, this one is {:?}",
+                session.archive, fingerprint
+            ))
+            .into());
+        }
+
+        // The session is valid, replace the current state.
+        self.groundtruth = groundtruth;
+        self.max_association_difference = max_association_difference;
+        self.associations = associations;
+        self.nb_dropped_frames = nb_dropped;
+        self.restore(session.tracker)?;
+        camera_path.restore(session.camera_path);
+        point_cloud.restore(session.point_cloud);
+        Ok(self.associations.len())
+    }
+}
+
+/// Private helpers, not exported to JavaScript.
+impl WasmTracker {
+    fn tracker(&self) -> Result<&track::Tracker, TrackerError> {
+        self.tracker
+            .as_ref()
+            .ok_or_else(TrackerError::not_initialized)
+    }
+
+    /// Initialize the tracker with the first frame of the loaded associations,
+    /// discarding previous keyframes and poses, and start a new edit log.
+    fn init_with(
+        &mut self,
+        mut calibration: Calibration,
+        params: TrackerParams,
+    ) -> Result<usize, TrackerError> {
+        // Setup tracking configuration.
+        params.validate()?;
+        calibration.validate()?;
+        let (depth_map, img) = self.read_frame(0)?;
+        let (height, width) = depth_map.shape();
+        if calibration.fit_resolution(width, height)? {
+            console_log!(
+                "Calibration intrinsics scaled to {}x{} images",
+                width,
+                height
+            );
+        }
+        let config = params.to_config(calibration.intrinsics(), calibration.depth_scale);
 
         // Initialize tracker with first depth and color image.
-        let (depth_map, img) =
-            _read_image_pair_bis(&self.associations[0], &self.tar_buffer, &self.entries)
-                .expect("81");
         let depth_time = self.associations[0].depth_timestamp;
         let img_time = self.associations[0].color_timestamp;
         let tracker = config.init(depth_time, &depth_map, img_time, img);
         let keyframe_img = tracker.keyframe_img();
         let keyframe_img = keyframe_img.transpose();
         update_kf_data(&mut self.current_keyframe_data, &keyframe_img);
-        self.keyframes.push(keyframe_img);
-        self.keyframes_candidates
-            .push(tracker.keyframe_candidates().to_owned());
+        self.keyframes = vec![keyframe_img];
+        self.keyframes_candidates = vec![tracker.keyframe_candidates().to_owned()];
+        self.keyframes_frame_ids = vec![0];
+        self.tracker_keyframe_id = 0;
 
         // Push initial pose to history.
         let (_, pose) = tracker.current_frame();
-        self.poses_history.push(pose);
-
+        self.poses_history = vec![pose];
+        self.p3p_poses.clear();
+        self.p3p_request = None;
+
+        self.edits = vec![Edit::Init {
+            calibration: calibration.clone(),
+            params,
+            max_association_difference: self.max_association_difference,
+        }];
+        self.calibration = Some(calibration);
         self.tracker = Some(tracker);
         self.change_keyframe = true;
 
         // Return the number of frames contained in the archive.
-        self.associations.len()
+        Ok(self.associations.len())
     }
 
-    pub fn pick_reference_kf_data(&mut self, index: usize) {
-        update_kf_data(&mut self.reference_keyframe_data, &self.keyframes[index]);
-        let (width, _) = self.keyframes[index].shape(); // DMatrix is transposed
-        let data = &mut self.reference_keyframe_data[..];
-        for &(x, y) in self.keyframes_candidates[index].iter() {
-            let pix = 4 * (y * width + x);
-            data[pix] = 255;
-            data[pix + 1] = 0;
-            data[pix + 2] = 0;
+    /// Apply an edit of a replayed log.
+    fn apply(&mut self, edit: Edit) -> Result<(), TrackerError> {
+        match edit {
+            Edit::Init { .. } => {
+                return Err(TrackerError::Decode(
+                    "Edit log contains more than one init edit".to_owned(),
+                ));
+            }
+            Edit::Track {
+                frame_id,
+                force_keyframe,
+            } => {
+                self.track_frame(frame_id, force_keyframe)?;
+            }
+            Edit::ResetAt {
+                base_frame_id,
+                last_tracked_frame_id,
+                keyframe_id,
+                params,
+            } => {
+                self.reset_tracker(
+                    base_frame_id,
+                    last_tracked_frame_id,
+                    keyframe_id,
+                    params.as_ref(),
+                )?;
+                self.edits.push(Edit::ResetAt {
+                    base_frame_id,
+                    last_tracked_frame_id,
+                    keyframe_id,
+                    params,
+                });
+            }
+            Edit::UpdateKeyframePose { keyframe_id, pose } => {
+                self.set_keyframe_pose(keyframe_id, pose.to_iso3())?;
+            }
+            Edit::ChooseP3pInitial {
+                id,
+                base_frame_id,
+                p3p,
+            } => {
+                // Hypotheses are recomputed from the clicked points.
+                self.store_p3p_hypotheses(p3p, Some(id))?;
+                self.choose_p3p(id, base_frame_id)?;
+            }
         }
+        Ok(())
     }
 
-    pub fn pick_current_kf_data(&mut self, index: usize) {
-        update_kf_data(&mut self.current_keyframe_data, &self.keyframes[index]);
-    }
-
-    pub fn reset_at(
+    /// Restart tracking from a previous frame, without recording it in the edit log.
+    fn reset_tracker(
         &mut self,
         base_frame_id: usize,
         last_tracked_frame_id: usize,
         keyframe_id: usize,
-    ) {
-        let config = self.tracker.as_ref().expect("reset_kf").config().clone();
-        let (depth_map, img) = _read_image_pair_bis(
-            &self.associations[base_frame_id],
-            &self.tar_buffer,
-            &self.entries,
-        )
-        .expect("81");
+        params: Option<&TrackerParams>,
+    ) -> Result<(), TrackerError> {
+        // Keyframes and poses are only discarded, never padded.
+        if keyframe_id > self.keyframes.len() {
+            return Err(TrackerError::IndexOutOfRange {
+                what: "keyframes",
+                index: keyframe_id,
+                len: self.keyframes.len(),
+            });
+        }
+        get_checked(&self.poses_history, last_tracked_frame_id, "poses_history")?;
+        let mut config = self.tracker()?.config().clone();
+        if let Some(params) = params {
+            params.validate()?;
+            config = params.to_config(config.intrinsics.clone(), config.depth_scale);
+        }
+        let (depth_map, img) = self.read_frame(base_frame_id)?;
         let depth_time = self.associations[base_frame_id].depth_timestamp;
         let img_time = self.associations[base_frame_id].color_timestamp;
         let mut tracker = config.init(depth_time, &depth_map, img_time, img);
         // Reset the pose to the one of the chosen keyframe.
-        let base_pose = self.poses_history[base_frame_id];
+        let base_pose = *get_checked(&self.poses_history, base_frame_id, "poses_history")?;
         tracker.reset_pose(base_pose, base_pose);
 
         let keyframe_img = tracker.keyframe_img();
         let keyframe_img = keyframe_img.transpose();
         update_kf_data(&mut self.current_keyframe_data, &keyframe_img);
-        self.keyframes.resize(keyframe_id, DMatrix::zeros(0, 0));
-        self.keyframes_candidates.resize(keyframe_id, vec![]);
-        self.poses_history
-            .resize(last_tracked_frame_id + 1, Iso3::identity());
+        self.keyframes.truncate(keyframe_id);
+        self.keyframes_candidates.truncate(keyframe_id);
+        self.keyframes_frame_ids.truncate(keyframe_id);
+        self.tracker_keyframe_id = base_frame_id;
+        self.poses_history.truncate(last_tracked_frame_id + 1);
         self.tracker = Some(tracker);
         self.change_keyframe = true;
+        Ok(())
     }
 
-    // pub fn reset_at_p3p(
-    //     &mut self,
-    //     base_frame_id: usize,
-    //     last_tracked_frame_id: usize,
-    //     keyframe_id: usize,
-    //     p3p_ref_points: JsValue,
-    //     p3p_key_points: JsValue,
-    // ) {
-    //     let config = self.tracker.as_ref().expect("reset_kf").config().clone();
-    //     let (depth_map, img) = _read_image_pair_bis(
-    //         &self.associations[base_frame_id],
-    //         &self.tar_buffer,
-    //         &self.entries,
-    //     )
-    //     .expect("81");
-    //     let depth_time = self.associations[base_frame_id].depth_timestamp;
-    //     let img_time = self.associations[base_frame_id].color_timestamp;
-    //     let mut tracker = config.init(depth_time, &depth_map, img_time, img);
-    //
-    //     // Reset the pose to the one of the chosen keyframe.
-    //     let pose = self.poses_history[base_frame_id];
-    //     tracker.reset_pose(pose, pose);
-    //
-    //     // Compute P3P initialization for the tracker.
-    //     let p3p_ref_points: [(f32, f32); 3] =
-    //         serde_wasm_bindgen::from_value(p3p_ref_points).expect("woops");
-    //     let p3p_key_points: [(f32, f32); 3] =
-    //         serde_wasm_bindgen::from_value(p3p_key_points).expect("woops");
-    //
-    //     // Identify 3D points associated to p3p_ref_points.
-    //     let ref_candidates_coords = tracker.keyframe_candidates();
-    //     let ref_candidates_idepths = tracker.keyframe_candidates_idepths();
-    //     let (closest_0, ref_0) = closest_to(p3p_ref_points[0], ref_candidates_coords);
-    //     let (closest_1, ref_1) = closest_to(p3p_ref_points[1], ref_candidates_coords);
-    //     let (closest_2, ref_2) = closest_to(p3p_ref_points[2], ref_candidates_coords);
-    //     console_log!("ref_0_pos: {:?}", ref_0);
-    //     console_log!("ref_1_pos: {:?}", ref_1);
-    //     console_log!("ref_2_pos: {:?}", ref_2);
-    //     let idepth_0 = ref_candidates_idepths[closest_0];
-    //     let idepth_1 = ref_candidates_idepths[closest_1];
-    //     let idepth_2 = ref_candidates_idepths[closest_2];
-    //     let intrinsics = &tracker.intrinsics()[1];
-    //     let to_camera_coords =
-    //         |(u, v), idepth| intrinsics.back_project(Point2::new(u, v), 1.0 / idepth);
-    //     let to_3d_world = |coords, idepth| pose * to_camera_coords(coords, idepth);
-    //     let world_3d_points = [
-    //         to_3d_world(ref_0, idepth_0).coords.into(),
-    //         to_3d_world(ref_1, idepth_1).coords.into(),
-    //         to_3d_world(ref_2, idepth_2).coords.into(),
-    //     ];
-    //
-    //     // Compute potential poses with P3P crate.
-    //     let bearing_vec_0 = to_camera_coords(p3p_key_points[0], 1.0).coords;
-    //     let bearing_vec_1 = to_camera_coords(p3p_key_points[1], 1.0).coords;
-    //     let bearing_vec_2 = to_camera_coords(p3p_key_points[2], 1.0).coords;
-    //     let bearing_vectors = [
-    //         ((1.0 / bearing_vec_0[2]) * bearing_vec_0).into(),
-    //         ((1.0 / bearing_vec_1[2]) * bearing_vec_1).into(),
-    //         ((1.0 / bearing_vec_2[2]) * bearing_vec_2).into(),
-    //     ];
-    //     let key_projections = p3p::nordberg::solve(&world_3d_points, &bearing_vectors);
-    //     // console_log!("reference pose: {}", pose);
-    //     console_log!("potential poses:");
-    //     let key_poses: Vec<_> = key_projections
-    //         .iter()
-    //         .map(|p| {
-    //             let rot_quat = Quaternion::from(Vec4::from(p.rotation));
-    //             let rot = UnitQuaternion::from_quaternion(rot_quat);
-    //             let trans = Translation::from(Vec3::from(p.translation));
-    //             let key_pose = Iso3::from_parts(trans, rot).inverse();
-    //             console_log!("{:?}", key_pose.translation);
-    //             key_pose
-    //         })
-    //         .collect();
-    //
-    //     // Select the one with lowest reprojection error.
-    //     let (_, retrack_img) = _read_image_pair_bis(
-    //         &self.associations[last_tracked_frame_id + 1],
-    //         &self.tar_buffer,
-    //         &self.entries,
-    //     )
-    //     .expect("ohoh");
-    //     key_poses.iter().for_each(|pose| {
-    //         let reproj_error = tracker.reprojection_error(pose, &retrack_img);
-    //         console_log!("reprojection error: {:?}", reproj_error);
-    //     });
-    //
-    //     // Update current frame pose of tracker with this one.
-    //     let estimated_pose = std::iter::once(&pose)
-    //         .chain(key_poses.iter())
-    //         .min_by(|p1, p2| {
-    //             let (inside_ratio_1, error_1) = tracker.reprojection_error(p1, &retrack_img);
-    //             let (inside_ratio_2, error_2) = tracker.reprojection_error(p2, &retrack_img);
-    //             if inside_ratio_2 > 0.25 {
-    //                 error_1.partial_cmp(&error_2).unwrap()
-    //             } else {
-    //                 std::cmp::Ordering::Less
-    //             }
-    //         })
-    //         .unwrap();
-    //     tracker.reset_pose(pose, *estimated_pose);
-    //
-    //     // Following is the same than the reset_at function.
-    //     let keyframe_img = tracker.keyframe_img();
-    //     let keyframe_img = keyframe_img.transpose();
-    //     update_kf_data(&mut self.current_keyframe_data, &keyframe_img);
-    //     self.keyframes.resize(keyframe_id, DMatrix::zeros(0, 0));
-    //     self.keyframes_candidates.resize(keyframe_id, vec![]);
-    //     self.poses_history
-    //         .resize(last_tracked_frame_id, Iso3::identity());
-    //
-    //     self.tracker = Some(tracker);
-    //     self.change_keyframe = true;
-    // }
-
-    pub fn p3p_visualize(
-        &mut self,
-        base_frame_id: usize,
-        last_tracked_frame_id: usize,
-        p3p_ref_points: JsValue,
-        p3p_key_points: JsValue,
-        point_cloud: &mut PointCloud,
-    ) -> JsValue {
-        let config = self.tracker.as_ref().expect("reset_kf").config().clone();
-        let (depth_map, img) = _read_image_pair_bis(
-            &self.associations[base_frame_id],
-            &self.tar_buffer,
-            &self.entries,
-        )
-        .expect("81");
+    /// Compute the P3P poses of the keyframe to reinitialize from points clicked
+    /// in it and in the reference keyframe, and the probability of each of them,
+    /// the current pose coming first in both.
+    fn p3p_hypotheses(&self, request: &P3pRequest) -> Result<P3pHypotheses, TrackerError> {
+        self.p3p_keyframe(request.base_frame_id, request.last_tracked_frame_id)?;
+        let base_frame_id = request.base_frame_id;
+        let last_tracked_frame_id = request.last_tracked_frame_id;
+        let config = self.tracker()?.config().clone();
+        let (depth_map, img) = self.read_frame(base_frame_id)?;
         let depth_time = self.associations[base_frame_id].depth_timestamp;
         let img_time = self.associations[base_frame_id].color_timestamp;
         let mut p3p_tracker = config.init(depth_time, &depth_map, img_time, img);
 
         // Reset the pose to the one of the chosen keyframe.
-        let base_pose = self.poses_history[base_frame_id];
-        let current_pose = self.poses_history[last_tracked_frame_id];
+        let base_pose = *get_checked(&self.poses_history, base_frame_id, "poses_history")?;
+        let current_pose =
+            *get_checked(&self.poses_history, last_tracked_frame_id, "poses_history")?;
         p3p_tracker.reset_pose(base_pose, current_pose);
+        let world_points = clicked_world_points(&p3p_tracker, &base_pose, &request.ref_points)?;
 
-        // Compute P3P initialization for the tracker.
-        let p3p_ref_points: [(f32, f32); 3] =
-            serde_wasm_bindgen::from_value(p3p_ref_points).expect("woops");
-        let p3p_key_points: [(f32, f32); 3] =
-            serde_wasm_bindgen::from_value(p3p_key_points).expect("woops");
-
-        // Identify 3D points associated to p3p_ref_points.
-        let ref_candidates_coords = p3p_tracker.keyframe_candidates();
-        let ref_candidates_idepths = p3p_tracker.keyframe_candidates_idepths();
-        let (closest_0, ref_0) = closest_to(p3p_ref_points[0], ref_candidates_coords);
-        let (closest_1, ref_1) = closest_to(p3p_ref_points[1], ref_candidates_coords);
-        let (closest_2, ref_2) = closest_to(p3p_ref_points[2], ref_candidates_coords);
-        console_log!("ref_0_pos: {:?}", ref_0);
-        console_log!("ref_1_pos: {:?}", ref_1);
-        console_log!("ref_2_pos: {:?}", ref_2);
-        let idepth_0 = ref_candidates_idepths[closest_0];
-        let idepth_1 = ref_candidates_idepths[closest_1];
-        let idepth_2 = ref_candidates_idepths[closest_2];
+        // Compute potential poses with P3P crate from exactly 3 points,
+        // or a single robust estimation with more points.
         let intrinsics = &p3p_tracker.intrinsics()[1];
-        let to_camera_coords =
-            |(u, v), idepth| intrinsics.back_project(Point2::new(u, v), 1.0 / idepth);
-        let to_3d_world = |coords, idepth| base_pose * to_camera_coords(coords, idepth);
-        let world_3d_points = [
-            to_3d_world(ref_0, idepth_0).coords.into(),
-            to_3d_world(ref_1, idepth_1).coords.into(),
-            to_3d_world(ref_2, idepth_2).coords.into(),
-        ];
-
-        // Compute potential poses with P3P crate.
-        let bearing_vec_0 = to_camera_coords(p3p_key_points[0], 1.0).coords;
-        let bearing_vec_1 = to_camera_coords(p3p_key_points[1], 1.0).coords;
-        let bearing_vec_2 = to_camera_coords(p3p_key_points[2], 1.0).coords;
-        let bearing_vectors = [
-            ((1.0 / bearing_vec_0[2]) * bearing_vec_0).into(),
-            ((1.0 / bearing_vec_1[2]) * bearing_vec_1).into(),
-            ((1.0 / bearing_vec_2[2]) * bearing_vec_2).into(),
-        ];
-        let key_projections = p3p::nordberg::solve(&world_3d_points, &bearing_vectors);
-        // console_log!("reference pose: {}", pose);
-        console_log!("potential poses:");
-        let key_poses: Vec<_> = key_projections
-            .iter()
-            .map(|p| {
-                let rot_quat = Quaternion::from(Vec4::from(p.rotation));
-                let rot = UnitQuaternion::from_quaternion(rot_quat);
-                let trans = Translation::from(Vec3::from(p.translation));
-                Iso3::from_parts(trans, rot).inverse()
-            })
-            .collect();
-
-        // Update the 3D points buffer with 3D points for each pose.
-        key_poses.iter().for_each(|&p3p_pose| {
-            console_log!("{:?}", p3p_pose.translation);
-            let mut temp_tracker = self.tracker.as_ref().unwrap().clone();
-            temp_tracker.reset_pose(p3p_pose, p3p_pose);
-            let p3ds = temp_tracker.points_3d();
-            let start = point_cloud.end;
-            point_cloud.end = start + 3 * p3ds.len();
-            point_cloud.sections.push((start, point_cloud.end));
-            let points = &mut point_cloud.points[start..point_cloud.end];
-            points.chunks_mut(3).zip(p3ds.iter()).for_each(|(p, p3d)| {
-                p[0] = p3d.x;
-                p[1] = p3d.y;
-                p[2] = p3d.z;
-            });
-        });
+        let (key_poses, rejected) = if request.key_points.len() == 3 {
+            let key_poses = p3p_poses(&world_points, &request.key_points, intrinsics);
+            (key_poses, vec![])
+        } else {
+            let solution =
+                pnp::solve(&world_points, &request.key_points, intrinsics, &request.pnp)?;
+            let rejected = (0..request.key_points.len())
+                .filter(|&i| !solution.inliers[i])
+                .collect();
+            (vec![solution.pose], rejected)
+        };
 
         // Compute reprojection error for each pose (+ current one).
         console_log!("last_tracked_frame_id: {}", last_tracked_frame_id);
-        let (_, retrack_img) = _read_image_pair_bis(
-            &self.associations[last_tracked_frame_id],
-            &self.tar_buffer,
-            &self.entries,
-        )
-        .expect("ohoh");
-        self.p3p_poses.clear();
-        let reproj_errors =
-            std::iter::once(&current_pose)
-                .chain(key_poses.iter())
-                .map(|p3p_pose| {
-                    self.p3p_poses.push(*p3p_pose);
-                    p3p_tracker.reprojection_error(p3p_pose, &retrack_img)
-                });
+        let (_, retrack_img) = self.read_frame(last_tracked_frame_id)?;
+        let poses: Vec<Iso3> = std::iter::once(current_pose).chain(key_poses).collect();
+        let reproj_errors = poses
+            .iter()
+            .map(|p3p_pose| p3p_tracker.reprojection_error(p3p_pose, &retrack_img));
 
         // Transform each reprojection error into a score.
         // Increase score if there is a low amount of inside points.
@@ -429,68 +794,585 @@ impl WasmTracker {
         // Higher score equals lower probability.
         let sum_scores: f32 = scores.iter().sum();
         let probabilities: Vec<_> = scores.iter().map(|s| s / sum_scores).collect();
-        serde_wasm_bindgen::to_value(&probabilities).expect("woops")
+        Ok(P3pHypotheses {
+            poses,
+            probabilities,
+            rejected,
+        })
+    }
+
+    /// Compute the P3P hypotheses of a request and keep them for `choose_p3p`.
+    /// Return the chosen hypothesis, the most probable unless `forced`, and all of them.
+    /// The forced hypothesis is checked before any change to the tracker.
+    fn store_p3p_hypotheses(
+        &mut self,
+        request: P3pRequest,
+        forced: Option<usize>,
+    ) -> Result<(usize, P3pHypotheses), TrackerError> {
+        let hypotheses = self.p3p_hypotheses(&request)?;
+        let hypothesis = select_hypothesis(&hypotheses.probabilities, forced)?;
+        self.p3p_poses = hypotheses.poses.clone();
+        self.p3p_request = Some(request);
+        Ok((hypothesis, hypotheses))
+    }
+
+    /// Id of the keyframe reinitialized by P3P,
+    /// the one tracked at `last_tracked_frame_id`, after `base_frame_id`.
+    fn p3p_keyframe(
+        &self,
+        base_frame_id: usize,
+        last_tracked_frame_id: usize,
+    ) -> Result<usize, TrackerError> {
+        let keyframe_id = self
+            .keyframes_frame_ids
+            .iter()
+            .position(|&frame_id| frame_id == last_tracked_frame_id)
+            .ok_or_else(|| {
+                TrackerError::Config(format!("Frame {} is not a keyframe", last_tracked_frame_id))
+            })?;
+        if base_frame_id >= last_tracked_frame_id {
+            return Err(TrackerError::Config(format!(
+                "Base frame {} must come before the reinitialized frame {}",
+                base_frame_id, last_tracked_frame_id
+            )));
+        }
+        Ok(keyframe_id)
+    }
+
+    /// Compute, check and keep the P3P hypotheses of a request,
+    /// choosing the most probable unless `forced`, without changing the tracker.
+    fn prepare_p3p_reset(
+        &mut self,
+        request: P3pRequest,
+        forced: Option<usize>,
+    ) -> Result<P3pReset, TrackerError> {
+        let keyframe_id =
+            self.p3p_keyframe(request.base_frame_id, request.last_tracked_frame_id)?;
+        let (hypothesis, hypotheses) = self.store_p3p_hypotheses(request, forced)?;
+        Ok(P3pReset {
+            keyframe_id,
+            hypothesis,
+            probabilities: hypotheses.probabilities,
+            rejected: hypotheses.rejected,
+        })
+    }
+
+    /// Restart tracking with the chosen P3P hypothesis of a request,
+    /// the most probable unless `forced`.
+    fn reset_p3p(
+        &mut self,
+        request: P3pRequest,
+        forced: Option<usize>,
+    ) -> Result<P3pReset, TrackerError> {
+        let base_frame_id = request.base_frame_id;
+        let reset = self.prepare_p3p_reset(request, forced)?;
+        self.choose_p3p(reset.hypothesis, base_frame_id)?;
+        Ok(reset)
     }
 
-    pub fn choose_p3p_initial(&mut self, id: usize, base_frame_id: usize) -> usize {
-        let keyframe_id = self.keyframes.len() - 1;
-        let last_tracked_frame_id = self.poses_history.len() - 1;
-        self.reset_at(base_frame_id, last_tracked_frame_id - 1, keyframe_id);
+    /// Restart tracking from `base_frame_id` with the P3P hypothesis `id`
+    /// as the pose of the keyframe at the `last_tracked_frame_id` of the request.
+    /// Return the id of the keyframe being reinitialized.
+    fn choose_p3p(&mut self, id: usize, base_frame_id: usize) -> Result<usize, TrackerError> {
+        let p3p_reset_pose = *get_checked(&self.p3p_poses, id, "p3p_poses")?;
+        let p3p = self
+            .p3p_request
+            .clone()
+            .ok_or_else(|| TrackerError::Tracking("No P3P hypothesis computed".to_owned()))?;
+        let keyframe_id = self.p3p_keyframe(base_frame_id, p3p.last_tracked_frame_id)?;
+        self.reset_tracker(
+            base_frame_id,
+            p3p.last_tracked_frame_id - 1,
+            keyframe_id,
+            None,
+        )?;
         let base_pose = self.poses_history[base_frame_id];
-        let p3p_reset_pose = self.p3p_poses[id];
-        let tracker = self.tracker.as_mut().unwrap();
+        let tracker = self
+            .tracker
+            .as_mut()
+            .ok_or_else(TrackerError::not_initialized)?;
         tracker.reset_pose(base_pose, p3p_reset_pose);
-        keyframe_id
+        self.edits.push(Edit::ChooseP3pInitial {
+            id,
+            base_frame_id,
+            p3p,
+        });
+        Ok(keyframe_id)
     }
 
-    pub fn track(&mut self, frame_id: usize, force_keyframe: bool) -> String {
+    /// Correct the pose of a keyframe in the tracked trajectory.
+    /// Frames tracked from it, up to the next keyframe, get the same correction.
+    /// The tracker is moved too if it tracks from that keyframe.
+    fn set_keyframe_pose(&mut self, kf_id: usize, pose: Iso3) -> Result<(), TrackerError> {
+        let frame_id = *get_checked(&self.keyframes_frame_ids, kf_id, "keyframes_frame_ids")?;
+        let next_frame_id = self
+            .keyframes_frame_ids
+            .get(kf_id + 1)
+            .map_or(self.poses_history.len(), |&id| {
+                id.min(self.poses_history.len())
+            });
+        let old_pose = *get_checked(&self.poses_history, frame_id, "poses_history")?;
+        let correction = pose * old_pose.inverse();
+        for frame_pose in &mut self.poses_history[frame_id..next_frame_id] {
+            *frame_pose = correction * *frame_pose;
+        }
+        self.poses_history[frame_id] = pose;
+        if frame_id == self.tracker_keyframe_id {
+            let current_pose = *self
+                .poses_history
+                .last()
+                .ok_or_else(TrackerError::not_initialized)?;
+            let tracker = self
+                .tracker
+                .as_mut()
+                .ok_or_else(TrackerError::not_initialized)?;
+            tracker.reset_pose(pose, current_pose);
+        }
+        self.edits.push(Edit::UpdateKeyframePose {
+            keyframe_id: kf_id,
+            pose: Pose::from(&pose),
+        });
+        Ok(())
+    }
+
+    fn track_frame(
+        &mut self,
+        frame_id: usize,
+        force_keyframe: bool,
+    ) -> Result<TrackingResult, TrackerError> {
+        let (depth_map, img) = self.read_frame(frame_id)?;
         let assoc = &self.associations[frame_id];
-        let (depth_map, img) =
-            _read_image_pair_bis(assoc, &self.tar_buffer, &self.entries).expect("92");
+        let keyframe_frame_id = self.tracker_keyframe_id;
+        let frame_img = img.clone();
 
         // Track the rgb-d image.
-        if let Some(ref mut t) = self.tracker {
-            self.change_keyframe = t.track(
-                force_keyframe,
+        let t = self
+            .tracker
+            .as_mut()
+            .ok_or_else(TrackerError::not_initialized)?;
+        self.change_keyframe = t.track(
+            force_keyframe,
+            assoc.depth_timestamp,
+            &depth_map,
+            assoc.color_timestamp,
+            img,
+        );
+        let (_, pose) = t.current_frame();
+        if self.change_keyframe {
+            let keyframe_img = t.keyframe_img();
+            let keyframe_img = keyframe_img.transpose();
+            self.keyframes.push(keyframe_img);
+            self.keyframes_candidates
+                .push(t.keyframe_candidates().to_owned());
+            self.keyframes_frame_ids.push(frame_id);
+            self.tracker_keyframe_id = frame_id;
+        }
+        self.poses_history.push(pose);
+        self.edits.push(Edit::Track {
+            frame_id,
+            force_keyframe,
+        });
+
+        // Return the structured tracking result.
+        let stats =
+            self.tracking_stats(keyframe_frame_id, self.change_keyframe, &pose, &frame_img)?;
+        Ok(TrackingResult {
+            frame_id,
+            color_timestamp: assoc.color_timestamp,
+            depth_timestamp: assoc.depth_timestamp,
+            pose: Pose::from(&pose),
+            keyframe_created: self.change_keyframe,
+            stats,
+        })
+    }
+
+    /// Statistics of a frame tracked at `pose` from the keyframe at `keyframe_frame_id`.
+    /// The tracker still holds that keyframe, unless the frame replaced it
+    /// with a new keyframe. It is then initialized again from the archive,
+    /// as when restoring a session, rather than cloning the tracker for every frame.
+    fn tracking_stats(
+        &self,
+        keyframe_frame_id: usize,
+        keyframe_created: bool,
+        pose: &Iso3,
+        img: &DMatrix<u8>,
+    ) -> Result<TrackingStats, TrackerError> {
+        let (inlier_ratio, residual) = if keyframe_created {
+            let (depth_map, keyframe_img) = self.read_frame(keyframe_frame_id)?;
+            let assoc = &self.associations[keyframe_frame_id];
+            let mut tracker = self.tracker()?.config().clone().init(
                 assoc.depth_timestamp,
                 &depth_map,
                 assoc.color_timestamp,
-                img,
+                keyframe_img,
             );
-            let (timestamp, pose) = t.current_frame();
-            if self.change_keyframe {
-                let keyframe_img = t.keyframe_img();
-                let keyframe_img = keyframe_img.transpose();
-                self.keyframes.push(keyframe_img);
-                self.keyframes_candidates
-                    .push(t.keyframe_candidates().to_owned());
+            let keyframe_pose =
+                *get_checked(&self.poses_history, keyframe_frame_id, "poses_history")?;
+            tracker.reset_pose(keyframe_pose, *pose);
+            tracker.reprojection_error(pose, img)
+        } else {
+            self.tracker()?.reprojection_error(pose, img)
+        };
+        Ok(TrackingStats {
+            residual,
+            iterations: None,
+            inlier_ratio,
+        })
+    }
+
+    /// Read associations.txt if present in the archive,
+    /// otherwise associate the images listed in rgb.txt and depth.txt
+    /// with the given maximum time difference.
+    /// Return the associations and the number of images left without association.
+    fn load_associations(
+        &self,
+        max_difference: f64,
+    ) -> Result<(Vec<tum_rgbd::Association>, usize), TrackerError> {
+        if self.entries.contains_key("associations.txt") {
+            let buffer = get_buffer("associations.txt", &self.tar_buffer, &self.entries)?;
+            return Ok((parse_associations_buf(buffer)?, 0));
+        }
+        associate::check_max_difference(max_difference)?;
+        let read_list = |name| {
+            let buffer = get_buffer(name, &self.tar_buffer, &self.entries)?;
+            let content =
+                std::str::from_utf8(buffer).map_err(|e| TrackerError::Decode(e.to_string()))?;
+            associate::parse_file_list(content)
+        };
+        let color_list = read_list("rgb.txt")?;
+        let depth_list = read_list("depth.txt")?;
+        let associations = associate::associate(&color_list, &depth_list, max_difference);
+        let nb_dropped = color_list.len() + depth_list.len() - 2 * associations.len();
+        console_log!(
+            "Associated {} frames, {} images dropped",
+            associations.len(),
+            nb_dropped
+        );
+        Ok((associations, nb_dropped))
+    }
+
+    /// Read groundtruth.txt if present in the archive.
+    fn load_groundtruth(&self) -> Result<Vec<(f64, Iso3)>, TrackerError> {
+        if !self.entries.contains_key("groundtruth.txt") {
+            return Ok(vec![]);
+        }
+        let buffer = get_buffer("groundtruth.txt", &self.tar_buffer, &self.entries)?;
+        let content =
+            std::str::from_utf8(buffer).map_err(|e| TrackerError::Decode(e.to_string()))?;
+        evaluation::parse_groundtruth(content)
+    }
+
+    /// Match tracked frames with groundtruth and align them.
+    fn aligned_trajectory(&self) -> Result<AlignedTrajectory, TrackerError> {
+        if self.groundtruth.is_empty() {
+            return Err(TrackerError::Archive(
+                "No groundtruth.txt in archive".to_owned(),
+            ));
+        }
+        let timestamps: Vec<f64> = self
+            .associations
+            .iter()
+            .map(|assoc| assoc.color_timestamp)
+            .collect();
+        AlignedTrajectory::new(&self.poses_history, &timestamps, &self.groundtruth)
+    }
+
+    /// Parse the first calibration file found at the root of the archive.
+    fn archive_calibration(&self) -> Result<Option<Calibration>, TrackerError> {
+        match CALIBRATION_FILES
+            .iter()
+            .find(|name| self.entries.contains_key(**name))
+        {
+            Some(name) => {
+                let buffer = get_buffer(name, &self.tar_buffer, &self.entries)?;
+                let content =
+                    std::str::from_utf8(buffer).map_err(|e| TrackerError::Decode(e.to_string()))?;
+                Calibration::parse(content).map(Some)
             }
-            self.poses_history.push(pose);
-            return (tum_rgbd::Frame { timestamp, pose }).to_string();
+            None => Ok(None),
+        }
+    }
+
+    fn fingerprint(&self) -> ArchiveFingerprint {
+        ArchiveFingerprint::new(&self.associations)
+    }
+
+    fn state(&self) -> Result<TrackerState, TrackerError> {
+        Ok(TrackerState {
+            calibration: self
+                .calibration
+                .clone()
+                .ok_or_else(TrackerError::not_initialized)?,
+            params: TrackerParams::from_config(self.tracker()?.config()),
+            max_association_difference: self.max_association_difference,
+            poses_history: self.poses_history.iter().map(Pose::from).collect(),
+            keyframes_frame_ids: self.keyframes_frame_ids.clone(),
+            tracker_keyframe_id: self.tracker_keyframe_id,
+            change_keyframe: self.change_keyframe,
+            edits: self.edits.clone(),
+        })
+    }
+
+    fn snapshot(&self) -> TrackerSnapshot {
+        TrackerSnapshot {
+            tracker: self.tracker.clone(),
+            calibration: self.calibration.clone(),
+            change_keyframe: self.change_keyframe,
+            keyframes: self.keyframes.clone(),
+            keyframes_candidates: self.keyframes_candidates.clone(),
+            keyframes_frame_ids: self.keyframes_frame_ids.clone(),
+            tracker_keyframe_id: self.tracker_keyframe_id,
+            poses_history: self.poses_history.clone(),
+            p3p_poses: self.p3p_poses.clone(),
+            p3p_request: self.p3p_request.clone(),
+            edits: self.edits.clone(),
+        }
+    }
+
+    /// Go back to a snapshot, the current state being returned.
+    fn swap_snapshot(&mut self, snapshot: TrackerSnapshot) -> TrackerSnapshot {
+        let current = self.snapshot();
+        if let Some(keyframe) = snapshot.keyframes.last() {
+            update_kf_data(&mut self.current_keyframe_data, keyframe);
+        }
+        self.tracker = snapshot.tracker;
+        self.calibration = snapshot.calibration;
+        self.change_keyframe = snapshot.change_keyframe;
+        self.keyframes = snapshot.keyframes;
+        self.keyframes_candidates = snapshot.keyframes_candidates;
+        self.keyframes_frame_ids = snapshot.keyframes_frame_ids;
+        self.tracker_keyframe_id = snapshot.tracker_keyframe_id;
+        self.poses_history = snapshot.poses_history;
+        self.p3p_poses = snapshot.p3p_poses;
+        self.p3p_request = snapshot.p3p_request;
+        self.edits = snapshot.edits;
+        current
+    }
+
+    /// Rebuild the keyframes and the tracker of a saved state.
+    /// The tracker is initialized on its keyframe,
+    /// with the pose of the last tracked frame as current pose.
+    fn restore(&mut self, state: TrackerState) -> Result<(), TrackerError> {
+        state.params.validate()?;
+        state.calibration.validate()?;
+        let config = state.params.to_config(
+            state.calibration.intrinsics(),
+            state.calibration.depth_scale,
+        );
+        let init_at = |frame_id: usize| -> Result<track::Tracker, TrackerError> {
+            let (depth_map, img) = self.read_frame(frame_id)?;
+            let assoc = &self.associations[frame_id];
+            Ok(config.clone().init(
+                assoc.depth_timestamp,
+                &depth_map,
+                assoc.color_timestamp,
+                img,
+            ))
         };
+        let poses_history: Vec<Iso3> = state.poses_history.iter().map(|p| p.to_iso3()).collect();
+        let mut keyframes = Vec::new();
+        let mut keyframes_candidates = Vec::new();
+        for &frame_id in state.keyframes_frame_ids.iter() {
+            let tracker = init_at(frame_id)?;
+            keyframes.push(tracker.keyframe_img().transpose());
+            keyframes_candidates.push(tracker.keyframe_candidates().to_owned());
+        }
+        let mut tracker = init_at(state.tracker_keyframe_id)?;
+        let keyframe_pose =
+            *get_checked(&poses_history, state.tracker_keyframe_id, "poses_history")?;
+        let current_pose = *poses_history
+            .last()
+            .ok_or_else(|| TrackerError::Decode("Session has no tracked frame".to_owned()))?;
+        tracker.reset_pose(keyframe_pose, current_pose);
+
+        if let Some(keyframe) = keyframes.last() {
+            update_kf_data(&mut self.current_keyframe_data, keyframe);
+        }
+        self.calibration = Some(state.calibration);
+        self.poses_history = poses_history;
+        self.keyframes = keyframes;
+        self.keyframes_candidates = keyframes_candidates;
+        self.keyframes_frame_ids = state.keyframes_frame_ids;
+        self.tracker_keyframe_id = state.tracker_keyframe_id;
+        self.change_keyframe = state.change_keyframe;
+        self.p3p_poses.clear();
+        self.p3p_request = None;
+        self.edits = state.edits;
+        self.tracker = Some(tracker);
+        Ok(())
+    }
 
-        // Return formatted camera pose.
-        unreachable!()
+    /// Read the depth and color images of the given frame.
+    fn read_frame(&self, frame_id: usize) -> Result<(DMatrix<u16>, DMatrix<u8>), TrackerError> {
+        let assoc = get_checked(&self.associations, frame_id, "associations")?;
+        _read_image_pair_bis(assoc, &self.tar_buffer, &self.entries)
     }
 }
 
-fn closest_to(point: (f32, f32), coords: &[(usize, usize)]) -> (usize, (f32, f32)) {
+/// Poses of the keyframe to reinitialize computed from clicked points,
+/// the current pose coming first.
+struct P3pHypotheses {
+    poses: Vec<Iso3>,
+    /// Probability of each pose.
+    probabilities: Vec<f32>,
+    /// Ids of the pairs of points rejected by robust PnP.
+    rejected: Vec<usize>,
+}
+
+/// Write timestamped poses in the TUM format, one "timestamp tx ty tz qx qy qz qw" per line.
+fn tum_trajectory(frames: impl Iterator<Item = (f64, Iso3)>) -> String {
+    frames
+        .map(|(timestamp, pose)| (tum_rgbd::Frame { timestamp, pose }).to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn closest_to(
+    point: (f32, f32),
+    coords: &[(usize, usize)],
+) -> Result<(usize, (f32, f32)), TrackerError> {
     let (id, &(u, v)) = coords
         .iter()
         .enumerate()
         .min_by(|&(_id1, &(u1, v1)), &(_id2, &(u2, v2))| {
             let d1 = (u1 as f32 - point.0).powi(2) + (v1 as f32 - point.1).powi(2);
             let d2 = (u2 as f32 - point.0).powi(2) + (v2 as f32 - point.1).powi(2);
-            d1.partial_cmp(&d2).unwrap()
+            d1.partial_cmp(&d2).unwrap_or(std::cmp::Ordering::Equal)
         })
-        .unwrap();
-    (id, (u as f32, v as f32))
+        .ok_or_else(|| TrackerError::Tracking("Keyframe has no candidate point".to_owned()))?;
+    Ok((id, (u as f32, v as f32)))
 }
 
-/// Update self.current_keyframe_data.
-/// The DMatrix in argument must already have been transposed to have the same
-/// components order in column major.
-fn update_kf_data(current_data: &mut [u8], kf: &DMatrix<u8>) {
+/// Read pairs of points clicked in the reference keyframe and in the keyframe
+/// to reinitialize, at least 3 of them.
+fn clicked_points(
+    ref_points: JsValue,
+    key_points: JsValue,
+) -> Result<(Vec<Pixel>, Vec<Pixel>), TrackerError> {
+    let ref_points: Vec<Pixel> = serde_wasm_bindgen::from_value(ref_points)
+        .map_err(|e| TrackerError::Config(format!("Invalid P3P reference points: {}", e)))?;
+    let key_points: Vec<Pixel> = serde_wasm_bindgen::from_value(key_points)
+        .map_err(|e| TrackerError::Config(format!("Invalid P3P keyframe points: {}", e)))?;
+    if ref_points.len() != key_points.len() || ref_points.len() < 3 {
+        return Err(TrackerError::Config(format!(
+            "Expected at least 3 pairs of points, got {} reference and {} keyframe points",
+            ref_points.len(),
+            key_points.len()
+        )));
+    }
+    Ok((ref_points, key_points))
+}
+
+/// Read the P3P hypothesis forced by the user, if any.
+fn forced_hypothesis(hypothesis: JsValue) -> Result<Option<usize>, TrackerError> {
+    if hypothesis.is_undefined() || hypothesis.is_null() {
+        return Ok(None);
+    }
+    let id = hypothesis
+        .as_f64()
+        .filter(|id| *id >= 0.0 && id.fract() == 0.0)
+        .ok_or_else(|| {
+            TrackerError::Config("P3P hypothesis must be a positive integer".to_owned())
+        })?;
+    Ok(Some(id as usize))
+}
+
+/// World coordinates of the keyframe candidates closest to points clicked in the keyframe.
+fn clicked_world_points(
+    tracker: &track::Tracker,
+    keyframe_pose: &Iso3,
+    clicked: &[(f32, f32)],
+) -> Result<Vec<Point3<f32>>, TrackerError> {
+    let candidates_coords = tracker.keyframe_candidates();
+    let candidates_idepths = tracker.keyframe_candidates_idepths();
+    let intrinsics = &tracker.intrinsics()[1];
+    clicked
+        .iter()
+        .map(|&point| {
+            let (closest, (u, v)) = closest_to(point, candidates_coords)?;
+            console_log!("ref_pos: {:?}", (u, v));
+            let idepth = candidates_idepths[closest];
+            Ok(keyframe_pose * intrinsics.back_project(Point2::new(u, v), 1.0 / idepth))
+        })
+        .collect()
+}
+
+/// Camera poses seeing 3 world points at the given pixels, computed with P3P crate.
+fn p3p_poses(
+    world_points: &[Point3<f32>],
+    key_points: &[(f32, f32)],
+    intrinsics: &Intrinsics,
+) -> Vec<Iso3> {
+    let to_camera_coords = |(u, v)| intrinsics.back_project(Point2::new(u, v), 1.0);
+    let bearing_vec_0 = to_camera_coords(key_points[0]).coords;
+    let bearing_vec_1 = to_camera_coords(key_points[1]).coords;
+    let bearing_vec_2 = to_camera_coords(key_points[2]).coords;
+    let bearing_vectors = [
+        ((1.0 / bearing_vec_0[2]) * bearing_vec_0).into(),
+        ((1.0 / bearing_vec_1[2]) * bearing_vec_1).into(),
+        ((1.0 / bearing_vec_2[2]) * bearing_vec_2).into(),
+    ];
+    let world_3d_points = [
+        world_points[0].coords.into(),
+        world_points[1].coords.into(),
+        world_points[2].coords.into(),
+    ];
+    let key_projections = p3p::nordberg::solve(&world_3d_points, &bearing_vectors);
+    console_log!("potential poses:");
+    key_projections
+        .iter()
+        .map(|p| {
+            let rot_quat = Quaternion::from(Vec4::from(p.rotation));
+            let rot = UnitQuaternion::from_quaternion(rot_quat);
+            let trans = Translation::from(Vec3::from(p.translation));
+            Iso3::from_parts(trans, rot).inverse()
+        })
+        .collect()
+}
+
+/// Index of the highest probability, the first one if they are not comparable.
+fn most_probable(probabilities: &[f32]) -> usize {
+    probabilities
+        .iter()
+        .enumerate()
+        .max_by(|(_, p1), (_, p2)| p1.partial_cmp(p2).unwrap_or(std::cmp::Ordering::Less))
+        .map_or(0, |(id, _)| id)
+}
+
+/// Hypothesis to restart from, the `forced` one if it exists,
+/// otherwise the most probable one.
+fn select_hypothesis(probabilities: &[f32], forced: Option<usize>) -> Result<usize, TrackerError> {
+    match forced {
+        Some(id) if id < probabilities.len() => Ok(id),
+        Some(id) => Err(TrackerError::Config(format!(
+            "P3P hypothesis {} does not exist, there are {} of them",
+            id,
+            probabilities.len()
+        ))),
+        None => Ok(most_probable(probabilities)),
+    }
+}
+
+/// Grayscale intensity of the 3D points of the keyframe.
+/// Points are those of `points_3d()`, in the order of the keyframe candidates.
+fn keyframe_points_intensities(tracker: &track::Tracker, nb_points: usize) -> Vec<u8> {
+    let img = tracker.keyframe_img();
+    let candidates = tracker.keyframe_candidates();
+    if candidates.len() == nb_points {
+        candidates.iter().map(|&(x, y)| img[(y, x)]).collect()
+    } else {
+        console_log!("Keyframe candidates do not match 3D points, using white");
+        vec![255; nb_points]
+    }
+}
+
+/// Update self.current_keyframe_data.
+/// The DMatrix in argument must already have been transposed to have the same
+/// components order in column major.
+/// The buffer is resized to the keyframe dimensions (RGBA, 4 bytes per pixel),
+/// so its pointer may change.
+fn update_kf_data(current_data: &mut Vec<u8>, kf: &DMatrix<u8>) {
+    current_data.resize(4 * kf.len(), 0);
     current_data
         .chunks_mut(4)
         .zip(kf.iter())
@@ -502,36 +1384,10 @@ fn update_kf_data(current_data: &mut [u8], kf: &DMatrix<u8>) {
         });
 }
 
-/// Create camera depending on `camera_id` command line argument.
-fn create_camera(camera_id: &str) -> Result<Intrinsics, String> {
-    match camera_id {
-        "fr1" => Ok(tum_rgbd::INTRINSICS_FR1),
-        "fr2" => Ok(tum_rgbd::INTRINSICS_FR2),
-        "fr3" => Ok(tum_rgbd::INTRINSICS_FR3),
-        "icl" => Ok(tum_rgbd::INTRINSICS_ICL_NUIM),
-        _ => {
-            // eprintln!("{}", USAGE);
-            Err(format!("Unknown camera id: {}", camera_id))
-        }
-    }
-}
-
 /// Open an association file (in bytes form) and parse it into a vector of Association.
-fn parse_associations_buf(buffer: &[u8]) -> Result<Vec<tum_rgbd::Association>, Box<dyn Error>> {
-    let mut content = String::new();
-    let mut slice = buffer;
-    slice.read_to_string(&mut content)?;
-    tum_rgbd::parse::associations(&content).map_err(|s| s.into())
-}
-
-struct FileEntry {
-    offset: usize,
-    length: usize,
-}
-
-fn get_buffer<'a>(name: &str, file: &'a [u8], entries: &HashMap<String, FileEntry>) -> &'a [u8] {
-    let entry = entries.get(name).expect("Entry is not in archive");
-    &file[entry.offset..entry.offset + entry.length]
+fn parse_associations_buf(buffer: &[u8]) -> Result<Vec<tum_rgbd::Association>, TrackerError> {
+    let content = std::str::from_utf8(buffer).map_err(|e| TrackerError::Decode(e.to_string()))?;
+    tum_rgbd::parse::associations(content).map_err(|s| TrackerError::Decode(s.to_string()))
 }
 
 /// Read a depth and color image given by an association.
@@ -539,17 +1395,19 @@ fn _read_image_pair(
     assoc: &tum_rgbd::Association,
     file: &[u8],
     entries: &HashMap<String, FileEntry>,
-) -> Result<(DMatrix<u16>, DMatrix<u8>), image::ImageError> {
+) -> Result<(DMatrix<u16>, DMatrix<u8>), TrackerError> {
+    let decode_err = |e: &dyn Error| TrackerError::Decode(e.to_string());
+
     // Read depth image.
-    let depth_path_str = assoc.depth_file_path.to_str().expect("oaea").to_owned();
-    let depth_buffer = get_buffer(&depth_path_str, file, entries);
-    let (w, h, depth_map_vec_u16) = _read_png_16bits_buf(depth_buffer)?;
+    let depth_buffer = get_buffer(entry_name(&assoc.depth_file_path)?, file, entries)?;
+    let (w, h, depth_map_vec_u16) =
+        _read_png_16bits_buf(depth_buffer).map_err(|e| decode_err(&e))?;
     let depth_map = DMatrix::from_row_slice(h, w, depth_map_vec_u16.as_slice());
 
     // Read color image.
-    let img_path_str = assoc.color_file_path.to_str().expect("oaeaauuu").to_owned();
-    let img_buffer = get_buffer(&img_path_str, file, entries);
-    let img = image::load(Cursor::new(img_buffer), image::ImageFormat::PNG)?;
+    let img_buffer = get_buffer(entry_name(&assoc.color_file_path)?, file, entries)?;
+    let img = image::load(Cursor::new(img_buffer), image::ImageFormat::PNG)
+        .map_err(|e| decode_err(&e))?;
     let img_mat = interop::matrix_from_image(img.to_luma());
 
     Ok((depth_map, img_mat))
@@ -559,19 +1417,22 @@ fn _read_image_pair_bis(
     assoc: &tum_rgbd::Association,
     file: &[u8],
     entries: &HashMap<String, FileEntry>,
-) -> Result<(DMatrix<u16>, DMatrix<u8>), Box<dyn Error>> {
+) -> Result<(DMatrix<u16>, DMatrix<u8>), TrackerError> {
     // Read depth image.
-    let depth_path_str = assoc.depth_file_path.to_str().expect("oaea").to_owned();
-    let depth_buffer = get_buffer(&depth_path_str, file, entries);
-    let (w, h, depth_map_vec_u16) = _png_decode_u16(depth_buffer)?;
+    let depth_path = entry_name(&assoc.depth_file_path)?;
+    let depth_buffer = get_buffer(depth_path, file, entries)?;
+    let (w, h, depth_map_vec_u16) = _png_decode_u16(depth_buffer)
+        .map_err(|e| TrackerError::Decode(format!("{}: {}", depth_path, e)))?;
     let depth_map = DMatrix::from_row_slice(h, w, depth_map_vec_u16.as_slice());
 
     // Read color image.
-    let img_path_str = assoc.color_file_path.to_str().expect("oaeaauuu").to_owned();
-    let img_buffer = get_buffer(&img_path_str, file, entries);
-    let png_img = png_me::decode_no_check(img_buffer)?;
+    let img_path = entry_name(&assoc.color_file_path)?;
+    let img_buffer = get_buffer(img_path, file, entries)?;
+    let png_img = png_me::decode_no_check(img_buffer)
+        .map_err(|e| TrackerError::Decode(format!("{}: {}", img_path, e)))?;
     let (width, height, data) = (png_img.width, png_img.height, png_img.data);
-    let img = image::RgbImage::from_raw(width as u32, height as u32, data).expect("toto");
+    let img = image::RgbImage::from_raw(width as u32, height as u32, data)
+        .ok_or_else(|| TrackerError::Decode(format!("{}: not an 8 bits RGB image", img_path)))?;
     let dyn_img = image::DynamicImage::ImageRgb8(img);
     let img_mat = interop::matrix_from_image(dyn_img.to_luma());
 
@@ -611,19 +1472,27 @@ fn _png_decode_u16(input: &[u8]) -> Result<(usize, usize, Vec<u16>), Box<dyn Err
 
 #[wasm_bindgen]
 pub struct CameraPath {
+    /// Camera positions buffer, growing when full. Values after `end` are not valid.
     poses: Vec<f32>,
     indices_kf: Vec<usize>,
     end: usize,
+    /// Incremented each time the poses buffer is reallocated.
+    generation: u32,
+    /// Full timestamped poses, one per frame in `poses`.
+    frames: Vec<(f64, Iso3)>,
 }
 
 #[wasm_bindgen]
 impl CameraPath {
+    /// Create a camera path with an initial capacity of `nb_frames`,
+    /// which may be 0 since the buffer grows on demand.
     pub fn new(nb_frames: usize) -> CameraPath {
-        assert!(nb_frames > 0);
         CameraPath {
             poses: vec![0.0; 3 * nb_frames],
             indices_kf: vec![],
             end: 0,
+            generation: 0,
+            frames: vec![],
         }
     }
 
@@ -631,6 +1500,27 @@ impl CameraPath {
         self.poses.as_ptr()
     }
 
+    /// Number of frames in the path.
+    pub fn len(&self) -> usize {
+        self.end / 3
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end == 0
+    }
+
+    /// Number of frames the current buffer can hold.
+    /// The JavaScript view on `poses()` should have `3 * capacity()` floats.
+    pub fn capacity(&self) -> usize {
+        self.poses.len() / 3
+    }
+
+    /// Generation of the poses buffer. When it changes, the buffer was reallocated
+    /// and JavaScript views on `poses()` must be recreated.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
     pub fn index_kf(&self, id: usize) -> usize {
         self.indices_kf[id] / 3
     }
@@ -641,15 +1531,25 @@ impl CameraPath {
         let last_tracked_frame = self.index_kf(kf_id) - 1;
         self.end = self.indices_kf[kf_id];
         self.indices_kf.resize(kf_id, 0);
+        self.frames.truncate(self.end / 3);
         last_tracked_frame
     }
 
-    pub fn tick(&mut self, wasm_tracker: &WasmTracker) {
-        let (_, pose) = wasm_tracker
-            .tracker
-            .as_ref()
-            .map(|t| t.current_frame())
-            .expect("current_frame");
+    /// Export the camera trajectory in the TUM format.
+    /// Each line is "timestamp tx ty tz qx qy qz qw".
+    pub fn export_tum_trajectory(&self) -> String {
+        tum_trajectory(self.frames.iter().cloned())
+    }
+
+    pub fn tick(&mut self, wasm_tracker: &WasmTracker) -> Result<(), JsValue> {
+        let (timestamp, pose) = wasm_tracker.tracker()?.current_frame();
+        if self.end + 3 > self.poses.len() {
+            // Double the buffer size, which may start empty.
+            let new_length = (2 * self.poses.len()).max(3);
+            self.poses.resize(new_length, 0.0);
+            self.generation += 1;
+            console_log!("CameraPath buffer grown to {} frames", new_length / 3);
+        }
         let translation = pose.translation.vector;
         self.poses[self.end] = translation.x;
         self.poses[self.end + 1] = translation.y;
@@ -657,7 +1557,149 @@ impl CameraPath {
         if wasm_tracker.change_keyframe {
             self.indices_kf.push(self.end);
         }
+        self.frames.push((timestamp, pose));
         self.end += 3;
+        Ok(())
+    }
+}
+
+impl CameraPath {
+    /// Move a keyframe of the path to a corrected pose,
+    /// and the frames tracked from it, up to the next keyframe, with the same correction.
+    fn set_keyframe_pose(&mut self, kf_id: usize, pose: Iso3) -> Result<(), TrackerError> {
+        let start = *get_checked(&self.indices_kf, kf_id, "indices_kf")? / 3;
+        let end = self
+            .indices_kf
+            .get(kf_id + 1)
+            .map_or(self.frames.len(), |&index| {
+                (index / 3).min(self.frames.len())
+            });
+        let old_pose = get_checked(&self.frames, start, "frames")?.1;
+        let correction = pose * old_pose.inverse();
+        for frame in start..end {
+            let frame_pose = if frame == start {
+                pose
+            } else {
+                correction * self.frames[frame].1
+            };
+            self.frames[frame].1 = frame_pose;
+            self.poses[3 * frame..3 * frame + 3]
+                .copy_from_slice(frame_pose.translation.vector.as_slice());
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> CameraPathState {
+        CameraPathState {
+            frames: self
+                .frames
+                .iter()
+                .map(|(timestamp, pose)| (*timestamp, Pose::from(pose)))
+                .collect(),
+            indices_kf: self.indices_kf.clone(),
+        }
+    }
+
+    /// Replace the path by a saved one, reallocating the poses buffer.
+    fn restore(&mut self, state: CameraPathState) {
+        self.frames = state
+            .frames
+            .iter()
+            .map(|(timestamp, pose)| (*timestamp, pose.to_iso3()))
+            .collect();
+        self.indices_kf = state.indices_kf;
+        let capacity = self.capacity().max(self.frames.len().next_power_of_two());
+        self.poses = vec![0.0; 3 * capacity];
+        for (p, (_, pose)) in self.poses.chunks_mut(3).zip(self.frames.iter()) {
+            p.copy_from_slice(pose.translation.vector.as_slice());
+        }
+        self.end = 3 * self.frames.len();
+        self.generation += 1;
+    }
+}
+
+// Groundtruth stuff ###########################################################
+
+/// Groundtruth trajectory expressed in the frame of the estimated trajectory,
+/// with the position error of each estimated frame.
+#[wasm_bindgen]
+pub struct GroundtruthPath {
+    positions: Vec<f32>,
+    errors: Vec<f32>,
+}
+
+impl Default for GroundtruthPath {
+    fn default() -> GroundtruthPath {
+        GroundtruthPath::new()
+    }
+}
+
+#[wasm_bindgen]
+impl GroundtruthPath {
+    pub fn new() -> GroundtruthPath {
+        GroundtruthPath {
+            positions: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// Groundtruth positions (3 floats per matched frame).
+    pub fn positions(&self) -> *const f32 {
+        self.positions.as_ptr()
+    }
+
+    /// Position error of each tracked frame, indexed like the CameraPath poses.
+    /// Frames without groundtruth have an error of -1.
+    pub fn errors(&self) -> *const f32 {
+        self.errors.as_ptr()
+    }
+
+    /// Number of groundtruth positions.
+    pub fn len(&self) -> usize {
+        self.positions.len() / 3
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Number of tracked frames in the errors buffer.
+    pub fn nb_frames(&self) -> usize {
+        self.errors.len()
+    }
+
+    /// Recompute the groundtruth path from the current tracked trajectory.
+    /// Groundtruth is aligned on the first tracked pose if `align_first_pose`,
+    /// otherwise by least squares on all positions.
+    /// Buffers may be reallocated, so their pointers must be queried again.
+    /// Return the number of groundtruth positions.
+    pub fn update(
+        &mut self,
+        wasm_tracker: &WasmTracker,
+        align_first_pose: bool,
+    ) -> Result<usize, JsValue> {
+        let trajectory = wasm_tracker.aligned_trajectory()?;
+        let est_to_gt = if align_first_pose {
+            trajectory.first_pose_alignment()
+        } else {
+            trajectory.alignment
+        };
+        let gt_to_est = est_to_gt.inverse();
+        self.positions.clear();
+        self.errors.clear();
+        self.errors.resize(wasm_tracker.poses_history.len(), -1.0);
+        for ((&frame_id, est), gt) in trajectory
+            .frame_ids
+            .iter()
+            .zip(trajectory.estimated.iter())
+            .zip(trajectory.groundtruth.iter())
+        {
+            let position = (gt_to_est * gt).translation.vector;
+            self.positions
+                .extend_from_slice(&[position.x, position.y, position.z]);
+            self.errors[frame_id] = (est.translation.vector - position).norm();
+        }
+        Ok(self.len())
     }
 }
 
@@ -667,7 +1709,30 @@ impl CameraPath {
 pub struct PointCloud {
     sections: Vec<(usize, usize)>,
     end: usize,
+    /// Points buffer, growing when full. Values after `end` are not valid.
     points: Vec<f32>,
+    /// Points in the camera coordinates of the keyframe of their section,
+    /// same indices as `points`.
+    local_points: Vec<f32>,
+    /// RGB color of each point, same indices as `points`.
+    /// There is no inverse depth variance per point: visual-odometry-rs gives
+    /// every candidate of a keyframe the configured `idepth_variance`,
+    /// and exposes no variance per candidate.
+    colors: Vec<u8>,
+    /// Number of keyframe points merged in each point, one value per point.
+    weights: Vec<u32>,
+    /// Pose of the camera of each section.
+    keyframe_poses: Vec<Iso3>,
+    /// Incremented each time the buffers are reallocated.
+    generation: u32,
+    /// Size of the voxels in which keyframe points are merged, if enabled.
+    voxel_size: Option<f32>,
+    /// Index of the point of each occupied voxel, and of the section of that point.
+    voxels: HashMap<[i32; 3], (usize, usize)>,
+    /// Points modified by each section, to undo the merges when it is discarded.
+    merges: Vec<Vec<Merge>>,
+    /// Start of the points modified outside of the last section.
+    modified_start: usize,
 }
 
 #[wasm_bindgen]
@@ -679,6 +1744,7 @@ pub struct Section {
 /// Public methods, exported to JavaScript.
 #[wasm_bindgen]
 impl PointCloud {
+    /// Create a point cloud with an initial capacity of `nb_points`.
     pub fn new(nb_points: usize) -> PointCloud {
         let sections = vec![];
         let points = vec![0.0; 3 * nb_points];
@@ -687,9 +1753,38 @@ impl PointCloud {
             sections,
             end: 0,
             points,
+            local_points: vec![0.0; 3 * nb_points],
+            colors: vec![0; 3 * nb_points],
+            weights: vec![0; nb_points],
+            keyframe_poses: vec![],
+            generation: 0,
+            voxel_size: None,
+            voxels: HashMap::new(),
+            merges: vec![],
+            modified_start: usize::MAX,
         }
     }
 
+    /// Number of points the current buffer can hold.
+    /// The JavaScript view on `points()` should have `3 * capacity()` floats.
+    pub fn capacity(&self) -> usize {
+        self.points.len() / 3
+    }
+
+    /// Generation of the buffers. When it changes, the buffers were reallocated
+    /// and JavaScript views on `points()` and `colors()` must be recreated.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Number of bytes used by the point cloud.
+    pub fn memory_usage(&self) -> usize {
+        (self.points.capacity() + self.local_points.capacity()) * std::mem::size_of::<f32>()
+            + self.colors.capacity()
+            + self.weights.capacity() * std::mem::size_of::<u32>()
+            + self.sections.capacity() * std::mem::size_of::<(usize, usize)>()
+    }
+
     pub fn section(&self, frame: usize) -> Section {
         let (start, end) = self.sections[frame];
         Section { start, end }
@@ -699,32 +1794,992 @@ impl PointCloud {
         self.points.as_ptr()
     }
 
+    /// RGB colors of the points (3 bytes per point, same indices as `points()`).
+    pub fn colors(&self) -> *const u8 {
+        self.colors.as_ptr()
+    }
+
+    /// Limit of valid points in buffer, as returned by `tick`.
+    pub fn end_valid(&self) -> usize {
+        self.end
+    }
+
+    /// Size of the voxels in which keyframe points are merged, 0 if disabled.
+    pub fn voxel_size(&self) -> f32 {
+        self.voxel_size.unwrap_or(0.0)
+    }
+
+    /// Merge the points of the next keyframes falling in the voxel of an existing point
+    /// into that point, averaging their positions and colors.
+    /// A voxel size of 0 disables merging.
+    /// Existing points are indexed in the new voxel grid but not merged together.
+    pub fn set_voxel_size(&mut self, voxel_size: f32) -> Result<(), JsValue> {
+        if voxel_size.is_nan() || voxel_size < 0.0 {
+            return Err(TrackerError::Config("voxel_size must be positive".to_owned()).into());
+        }
+        self.voxels.clear();
+        if voxel_size == 0.0 {
+            self.voxel_size = None;
+            return Ok(());
+        }
+        self.voxel_size = Some(voxel_size);
+        for section in 0..self.nb_valid_sections() {
+            self.index_section(section, voxel_size);
+        }
+        Ok(())
+    }
+
+    /// Start of the points modified by merges (or reverted by `reset_kf`)
+    /// since the last call, or `usize::MAX` if none.
+    /// New sections are not included, they are after the previous limit of valid points.
+    pub fn take_modified_start(&mut self) -> usize {
+        std::mem::replace(&mut self.modified_start, usize::MAX)
+    }
+
     /// Reset point cloud as if given keyframe was the last one.
+    /// Points merged by the discarded keyframes get back their previous attributes.
     /// Return the limit of valid points in buffer.
     pub fn reset_kf(&mut self, kf_id: usize) -> usize {
+        let discarded = kf_id.min(self.merges.len());
+        let discarded_merges: Vec<_> = self.merges.drain(discarded..).collect();
+        for merges in discarded_merges.iter().rev() {
+            for merge in merges.iter().rev() {
+                let i = merge.point;
+                self.set_local_point(i, merge.section, &merge.local_position);
+                self.colors[3 * i..3 * i + 3].copy_from_slice(&merge.color);
+                self.weights[i] = merge.weight;
+                self.modified_start = self.modified_start.min(3 * i);
+            }
+        }
         self.sections.resize(kf_id, (0, 0));
+        self.keyframe_poses.truncate(kf_id);
         self.end = self.sections[kf_id - 1].1;
+        let end = self.end;
+        self.voxels.retain(|_, &mut (i, _)| 3 * i < end);
         self.end
     }
 
-    pub fn tick(&mut self, wasm_tracker: &WasmTracker) -> usize {
+    /// Pose of the camera of a keyframe, `{ translation, rotation }`
+    /// with the rotation quaternion in (x, y, z, w) order.
+    pub fn keyframe_pose(&self, kf_id: usize) -> Result<JsValue, JsValue> {
+        let pose = get_checked(&self.keyframe_poses, kf_id, "keyframe_poses")?;
+        Ok(to_js_value(&Pose::from(pose))?)
+    }
+
+    /// Export the valid points with their colors in the OBJ format,
+    /// with the camera frustum of each keyframe drawn as lines.
+    /// `frustum_depth` is the distance of the frustum image plane to its camera center.
+    pub fn export_obj(
+        &self,
+        wasm_tracker: &WasmTracker,
+        frustum_depth: f32,
+    ) -> Result<String, JsValue> {
+        let calibration = wasm_tracker
+            .calibration
+            .as_ref()
+            .ok_or_else(TrackerError::not_initialized)?;
+        let frustums: Vec<_> = self.keyframe_poses[..self.nb_valid_sections()]
+            .iter()
+            .map(|pose| calibration.frustum(pose, frustum_depth))
+            .collect();
+        let points = &self.points[..self.end];
+        let colors = &self.colors[..self.end];
+        Ok(export::write_obj(points, colors, &[], &frustums))
+    }
+
+    /// Export the valid points with their colors in the PLY format.
+    /// If `with_normals`, each point normal is oriented toward the camera
+    /// of the keyframe it comes from.
+    pub fn export_ply(&self, binary: bool, with_normals: bool) -> Vec<u8> {
+        let points = &self.points[..self.end];
+        let colors = &self.colors[..self.end];
+        if with_normals {
+            let normals = self.view_normals();
+            export::write_ply(points, colors, Some(&normals), &[], binary)
+        } else {
+            export::write_ply(points, colors, None, &[], binary)
+        }
+    }
+
+    pub fn tick(&mut self, wasm_tracker: &WasmTracker) -> Result<usize, JsValue> {
         if wasm_tracker.change_keyframe {
             // unimplemented!();
             console_log!("new keyframe");
-            let start = self.end;
-            let points_3d = wasm_tracker.tracker.as_ref().expect("tracker").points_3d();
-            self.end = start + 3 * points_3d.len();
-            self.sections.push((start, self.end));
-            let points = &mut self.points[start..self.end];
-            points
-                .chunks_mut(3)
-                .zip(points_3d.iter())
-                .for_each(|(p, p3d)| {
-                    p[0] = p3d.x;
-                    p[1] = p3d.y;
-                    p[2] = p3d.z;
-                });
+            self.push_keyframe(wasm_tracker.tracker()?, true);
         }
-        self.end
+        Ok(self.end)
+    }
+}
+
+impl PointCloud {
+    /// Correct the pose of a keyframe, and move the points of its section accordingly.
+    /// Points of later keyframes merged into this section move with it.
+    /// Return the updated section.
+    fn update_keyframe_pose(&mut self, kf_id: usize, pose: Iso3) -> Result<Section, TrackerError> {
+        let (start, end) = *get_checked(&self.sections, kf_id, "sections")?;
+        *get_checked_mut(&mut self.keyframe_poses, kf_id, "keyframe_poses")? = pose;
+        let end = end.min(self.end);
+        for i in start / 3..end / 3 {
+            let local = self.local_point(i);
+            self.points[3 * i..3 * i + 3].copy_from_slice((pose * local).coords.as_slice());
+        }
+        self.modified_start = self.modified_start.min(start);
+
+        // Index the moved points in their new voxels.
+        if let Some(voxel_size) = self.voxel_size {
+            self.voxels.retain(|_, &mut (_, section)| section != kf_id);
+            self.index_section(kf_id, voxel_size);
+        }
+        Ok(Section { start, end })
+    }
+
+    /// Number of sections, with their keyframe pose, entirely in the valid points.
+    fn nb_valid_sections(&self) -> usize {
+        self.sections
+            .iter()
+            .zip(self.keyframe_poses.iter())
+            .take_while(|(&(_, end), _)| end <= self.end)
+            .count()
+    }
+
+    /// Unit vectors from each valid point to the camera of its keyframe.
+    fn view_normals(&self) -> Vec<f32> {
+        let mut normals = vec![0.0; self.end];
+        let nb_sections = self.nb_valid_sections();
+        let sections = self.sections.iter().zip(self.keyframe_poses.iter());
+        for (&(start, end), pose) in sections.take(nb_sections) {
+            let center = pose.translation.vector;
+            let section_points = self.points[start..end].chunks(3);
+            let section_normals = normals[start..end].chunks_mut(3);
+            for (p, n) in section_points.zip(section_normals) {
+                let normal = (center - Vec3::new(p[0], p[1], p[2])).normalize();
+                n.copy_from_slice(normal.as_slice());
+            }
+        }
+        normals
+    }
+
+    /// Append the 3D points of the tracker keyframe in a new section after the valid ones.
+    /// If `merge` and a voxel size is set, points falling in the voxel of an existing point
+    /// are merged into it instead.
+    fn push_keyframe(&mut self, tracker: &track::Tracker, merge: bool) {
+        let (_, keyframe_pose) = tracker.current_frame();
+        let world_to_camera = keyframe_pose.inverse();
+        self.keyframe_poses.push(keyframe_pose);
+        let points_3d = tracker.points_3d();
+        let intensities = keyframe_points_intensities(tracker, points_3d.len());
+        let start = self.end;
+        self.reserve(start + 3 * points_3d.len());
+        let section = self.sections.len();
+        self.sections.push((start, start));
+        let voxel_size = self.voxel_size.filter(|_| merge);
+        let mut merges = Vec::new();
+        for (p3d, &intensity) in points_3d.iter().zip(intensities.iter()) {
+            let voxel = voxel_size.map(|size| voxel_index(p3d, size));
+            match voxel.and_then(|v| self.voxels.get(&v).cloned()) {
+                Some((i, i_section)) => merges.push(self.merge_point(i, i_section, p3d, intensity)),
+                None => {
+                    let i = self.end / 3;
+                    self.points[3 * i..3 * i + 3].copy_from_slice(&[p3d.x, p3d.y, p3d.z]);
+                    let local = world_to_camera * p3d;
+                    self.local_points[3 * i..3 * i + 3].copy_from_slice(local.coords.as_slice());
+                    self.colors[3 * i..3 * i + 3].copy_from_slice(&[intensity; 3]);
+                    self.weights[i] = 1;
+                    if let Some(v) = voxel {
+                        self.voxels.insert(v, (i, section));
+                    }
+                    self.end += 3;
+                    self.sections.last_mut().unwrap().1 = self.end;
+                }
+            }
+        }
+        self.merges.push(merges);
+    }
+
+    /// Average a keyframe point into the existing point `i` of `section`.
+    /// Return the previous attributes of the point.
+    fn merge_point(&mut self, i: usize, section: usize, p3d: &Point3<f32>, intensity: u8) -> Merge {
+        let mut merge = Merge {
+            point: i,
+            section,
+            local_position: [0.0; 3],
+            color: [0; 3],
+            weight: self.weights[i],
+        };
+        merge
+            .local_position
+            .copy_from_slice(&self.local_points[3 * i..3 * i + 3]);
+        merge.color.copy_from_slice(&self.colors[3 * i..3 * i + 3]);
+        let w = merge.weight as f32;
+        let average = |old: f32, new: f32| (w * old + new) / (w + 1.0);
+        let position = &self.points[3 * i..3 * i + 3];
+        let averaged = Point3::new(
+            average(position[0], p3d.x),
+            average(position[1], p3d.y),
+            average(position[2], p3d.z),
+        );
+        let local = self.keyframe_poses[section].inverse() * averaged;
+        self.set_local_point(i, section, &[local.x, local.y, local.z]);
+        for k in 0..3 {
+            self.colors[3 * i + k] =
+                average(f32::from(merge.color[k]), f32::from(intensity)).round() as u8;
+        }
+        self.weights[i] += 1;
+        self.modified_start = self.modified_start.min(3 * i);
+        merge
+    }
+
+    /// Index the points of a section in the voxels not occupied yet.
+    fn index_section(&mut self, section: usize, voxel_size: f32) {
+        let (start, end) = self.sections[section];
+        for i in start / 3..end.min(self.end) / 3 {
+            let p = &self.points[3 * i..3 * i + 3];
+            let voxel = voxel_index(&Point3::new(p[0], p[1], p[2]), voxel_size);
+            self.voxels.entry(voxel).or_insert((i, section));
+        }
+    }
+
+    fn local_point(&self, i: usize) -> Point3<f32> {
+        let l = &self.local_points[3 * i..3 * i + 3];
+        Point3::new(l[0], l[1], l[2])
+    }
+
+    /// Set the camera coordinates of point `i` of `section`
+    /// and update its world coordinates.
+    fn set_local_point(&mut self, i: usize, section: usize, local: &[f32; 3]) {
+        self.local_points[3 * i..3 * i + 3].copy_from_slice(local);
+        let world = self.keyframe_poses[section] * self.local_point(i);
+        self.points[3 * i..3 * i + 3].copy_from_slice(world.coords.as_slice());
+    }
+
+    fn state(&self) -> PointCloudState {
+        let nb_points = self.end / 3;
+        PointCloudState {
+            sections: self.sections.clone(),
+            points: self.points[..self.end].to_vec(),
+            local_points: self.local_points[..self.end].to_vec(),
+            colors: self.colors[..self.end].to_vec(),
+            weights: self.weights[..nb_points].to_vec(),
+            keyframe_poses: self.keyframe_poses.iter().map(Pose::from).collect(),
+            voxel_size: self.voxel_size,
+            voxels: self
+                .voxels
+                .iter()
+                .map(|(&v, &(i, section))| (v, i, section))
+                .collect(),
+            merges: self.merges.clone(),
+        }
+    }
+
+    /// Replace the point cloud by a saved one, reallocating the buffers.
+    fn restore(&mut self, state: PointCloudState) {
+        self.end = state.points.len();
+        let length = self.points.len().max(self.end);
+        self.points = state.points;
+        self.points.resize(length, 0.0);
+        self.local_points = state.local_points;
+        self.local_points.resize(length, 0.0);
+        self.colors = state.colors;
+        self.colors.resize(length, 0);
+        self.weights = state.weights;
+        self.weights.resize(length / 3, 0);
+        self.sections = state.sections;
+        self.keyframe_poses = state.keyframe_poses.iter().map(|p| p.to_iso3()).collect();
+        self.voxel_size = state.voxel_size;
+        self.voxels = state
+            .voxels
+            .into_iter()
+            .map(|(v, i, section)| (v, (i, section)))
+            .collect();
+        self.merges = state.merges;
+        self.modified_start = usize::MAX;
+        self.generation += 1;
+    }
+
+    /// Grow the buffers (at least doubling them) to hold `length` floats of points.
+    fn reserve(&mut self, length: usize) {
+        if length > self.points.len() {
+            let new_length = length.max(2 * self.points.len());
+            self.points.resize(new_length, 0.0);
+            self.local_points.resize(new_length, 0.0);
+            self.colors.resize(new_length, 0);
+            self.weights.resize(new_length / 3, 0);
+            self.generation += 1;
+            console_log!(
+                "PointCloud buffer grown to {} points ({} MB)",
+                new_length / 3,
+                self.memory_usage() / 1_000_000
+            );
+        }
+    }
+}
+
+// TSDF stuff ##################################################################
+
+/// Dense surface reconstruction, fusing the full depth maps of the keyframes
+/// in a truncated signed distance function (TSDF) volume.
+#[wasm_bindgen]
+pub struct TsdfReconstruction {
+    volume: TsdfVolume,
+    /// Frame id and pose of the keyframes fused in the volume.
+    integrated: Vec<(usize, Iso3)>,
+}
+
+#[wasm_bindgen]
+impl TsdfReconstruction {
+    /// `params` is an optional object `{ voxel_size, truncation, max_depth, pixel_step }`,
+    /// with distances in meters.
+    pub fn new(params: JsValue) -> Result<TsdfReconstruction, JsValue> {
+        Ok(TsdfReconstruction {
+            volume: TsdfVolume::new(TsdfParams::from_js(params)?),
+            integrated: vec![],
+        })
+    }
+
+    /// Number of keyframes fused in the volume.
+    pub fn nb_keyframes(&self) -> usize {
+        self.integrated.len()
+    }
+
+    /// Number of allocated voxels.
+    pub fn nb_voxels(&self) -> usize {
+        self.volume.nb_voxels()
+    }
+
+    /// Fuse at most `max_keyframes` of the keyframes tracked since the last update,
+    /// so that fusion can be spread over several animation frames.
+    /// The volume is rebuilt from scratch if previously fused keyframes
+    /// were discarded or moved by a reset.
+    /// Return the number of keyframes still to fuse.
+    pub fn update(
+        &mut self,
+        wasm_tracker: &WasmTracker,
+        max_keyframes: usize,
+    ) -> Result<usize, JsValue> {
+        let calibration = wasm_tracker
+            .calibration
+            .as_ref()
+            .ok_or_else(TrackerError::not_initialized)?;
+        let keyframes: Vec<(usize, Iso3)> = wasm_tracker
+            .keyframes_frame_ids
+            .iter()
+            .map(|&id| {
+                Ok((
+                    id,
+                    *get_checked(&wasm_tracker.poses_history, id, "poses_history")?,
+                ))
+            })
+            .collect::<Result<_, TrackerError>>()?;
+        if !keyframes.starts_with(&self.integrated) {
+            console_log!("Keyframes changed, rebuilding the TSDF volume");
+            self.volume.clear();
+            self.integrated.clear();
+        }
+        let pending = &keyframes[self.integrated.len()..];
+        for &(frame_id, pose) in pending.iter().take(max_keyframes) {
+            let (depth_map, img) = wasm_tracker.read_frame(frame_id)?;
+            self.volume.integrate(&depth_map, &img, &pose, calibration);
+            self.integrated.push((frame_id, pose));
+        }
+        if !pending.is_empty() && max_keyframes > 0 {
+            console_log!(
+                "TSDF volume: {} keyframes, {} voxels",
+                self.integrated.len(),
+                self.volume.nb_voxels()
+            );
+        }
+        Ok(keyframes.len() - self.integrated.len())
+    }
+
+    /// Extract the surface mesh with marching cubes and export it in the PLY format.
+    pub fn export_ply(&self, binary: bool) -> Vec<u8> {
+        let mesh = self.volume.extract_mesh();
+        export::write_ply(&mesh.vertices, &mesh.colors, None, &mesh.faces, binary)
+    }
+
+    /// Extract the surface mesh with marching cubes and export it in the OBJ format.
+    pub fn export_obj(&self) -> String {
+        let mesh = self.volume.extract_mesh();
+        export::write_obj(&mesh.vertices, &mesh.colors, &mesh.faces, &[])
+    }
+}
+
+// Undo stuff ##################################################################
+
+/// State of the WasmTracker kept in memory to undo a correction.
+/// Unlike a session, the tracker and keyframe images are cloned,
+/// so restoring it does not read the archive again.
+struct TrackerSnapshot {
+    tracker: Option<track::Tracker>,
+    calibration: Option<Calibration>,
+    change_keyframe: bool,
+    keyframes: Vec<DMatrix<u8>>,
+    keyframes_candidates: Vec<Vec<(usize, usize)>>,
+    keyframes_frame_ids: Vec<usize>,
+    tracker_keyframe_id: usize,
+    poses_history: Vec<Iso3>,
+    p3p_poses: Vec<Iso3>,
+    p3p_request: Option<P3pRequest>,
+    edits: Vec<Edit>,
+}
+
+/// Snapshot of the tracker, camera path and point cloud.
+struct Snapshot {
+    tracker: TrackerSnapshot,
+    camera_path: CameraPathState,
+    point_cloud: PointCloudState,
+}
+
+/// Undo and redo stacks of snapshots, recorded before each correction
+/// (`reset_at`, `choose_p3p_initial`, keyframe pose updates).
+#[wasm_bindgen]
+pub struct UndoHistory {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// Maximum number of snapshots kept for undo, the oldest ones are dropped.
+    /// Each snapshot holds a copy of the keyframe images and of the point cloud.
+    pub max_snapshots: usize,
+}
+
+#[wasm_bindgen]
+impl UndoHistory {
+    pub fn new(max_snapshots: usize) -> UndoHistory {
+        UndoHistory {
+            undo: vec![],
+            redo: vec![],
+            max_snapshots,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Drop all snapshots, for example when another sequence is loaded.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Record the current state before a correction.
+    /// Corrections undone until now can not be redone anymore.
+    pub fn record(
+        &mut self,
+        wasm_tracker: &WasmTracker,
+        camera_path: &CameraPath,
+        point_cloud: &PointCloud,
+    ) {
+        self.redo.clear();
+        self.undo.push(Snapshot {
+            tracker: wasm_tracker.snapshot(),
+            camera_path: camera_path.state(),
+            point_cloud: point_cloud.state(),
+        });
+        if self.undo.len() > self.max_snapshots {
+            let nb_dropped = self.undo.len() - self.max_snapshots;
+            self.undo.drain(..nb_dropped);
+        }
+    }
+
+    /// Go back to the state before the last recorded correction.
+    /// Buffers of the camera path and point cloud are reallocated.
+    /// Return false if there is nothing to undo.
+    pub fn undo(
+        &mut self,
+        wasm_tracker: &mut WasmTracker,
+        camera_path: &mut CameraPath,
+        point_cloud: &mut PointCloud,
+    ) -> bool {
+        match self.undo.pop() {
+            Some(snapshot) => {
+                let current = snapshot.apply(wasm_tracker, camera_path, point_cloud);
+                self.redo.push(current);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Apply again the last undone correction.
+    /// Return false if there is nothing to redo.
+    pub fn redo(
+        &mut self,
+        wasm_tracker: &mut WasmTracker,
+        camera_path: &mut CameraPath,
+        point_cloud: &mut PointCloud,
+    ) -> bool {
+        match self.redo.pop() {
+            Some(snapshot) => {
+                let current = snapshot.apply(wasm_tracker, camera_path, point_cloud);
+                self.undo.push(current);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Snapshot {
+    /// Restore the snapshot, returning one of the current state.
+    fn apply(
+        self,
+        wasm_tracker: &mut WasmTracker,
+        camera_path: &mut CameraPath,
+        point_cloud: &mut PointCloud,
+    ) -> Snapshot {
+        let current = Snapshot {
+            tracker: wasm_tracker.swap_snapshot(self.tracker),
+            camera_path: camera_path.state(),
+            point_cloud: point_cloud.state(),
+        };
+        camera_path.restore(self.camera_path);
+        point_cloud.restore(self.point_cloud);
+        current
+    }
+}
+
+// Branches stuff ##############################################################
+
+/// A trajectory kept when restarting from a previous keyframe.
+struct Branch {
+    name: String,
+    /// State of the branch, `None` for the active one,
+    /// whose state is the one of the tracker, camera path and point cloud.
+    snapshot: Option<Snapshot>,
+}
+
+/// Maximum number of branches, each one holds a copy of the tracker and point cloud.
+/// Beyond it, keeping a branch drops the oldest inactive one.
+const MAX_BRANCHES: usize = 8;
+
+/// Alternative trajectories of the sequence.
+/// Before a restart, the current trajectory can be kept in a new named branch,
+/// so it can be compared to the corrected one, exported or switched to later.
+#[wasm_bindgen]
+pub struct TrajectoryBranches {
+    branches: Vec<Branch>,
+    active: usize,
+}
+
+impl Default for TrajectoryBranches {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl TrajectoryBranches {
+    /// Start with a single active branch named "main".
+    pub fn new() -> TrajectoryBranches {
+        TrajectoryBranches {
+            branches: vec![Branch {
+                name: "main".to_owned(),
+                snapshot: None,
+            }],
+            active: 0,
+        }
+    }
+
+    /// Id of the active branch.
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Keep a copy of the current trajectory in a new named branch, before a restart.
+    /// The active branch does not change. Return the id of the new branch.
+    /// Beyond `MAX_BRANCHES`, the oldest inactive branch is dropped,
+    /// shifting the ids of the following ones.
+    pub fn keep(
+        &mut self,
+        name: &str,
+        wasm_tracker: &WasmTracker,
+        camera_path: &CameraPath,
+        point_cloud: &PointCloud,
+    ) -> Result<usize, JsValue> {
+        if name.is_empty() {
+            return Err(TrackerError::Config("Branch name is empty".to_owned()).into());
+        }
+        if self.branches.iter().any(|b| b.name == name) {
+            return Err(TrackerError::Config(format!("Branch {} already exists", name)).into());
+        }
+        if self.branches.len() >= MAX_BRANCHES {
+            let oldest = if self.active == 0 { 1 } else { 0 };
+            self.branches.remove(oldest);
+            if oldest < self.active {
+                self.active -= 1;
+            }
+        }
+        self.branches.push(Branch {
+            name: name.to_owned(),
+            snapshot: Some(Snapshot {
+                tracker: wasm_tracker.snapshot(),
+                camera_path: camera_path.state(),
+                point_cloud: point_cloud.state(),
+            }),
+        });
+        Ok(self.branches.len() - 1)
+    }
+
+    /// List of the branches, `[{ id, name, active, nb_frames, nb_keyframes }]`.
+    pub fn list(&self, wasm_tracker: &WasmTracker) -> Result<JsValue, JsValue> {
+        let infos: Vec<_> = self
+            .branches
+            .iter()
+            .enumerate()
+            .map(|(id, branch)| {
+                let (nb_frames, nb_keyframes) = match &branch.snapshot {
+                    Some(snapshot) => (
+                        snapshot.tracker.poses_history.len(),
+                        snapshot.tracker.keyframes.len(),
+                    ),
+                    None => (
+                        wasm_tracker.poses_history.len(),
+                        wasm_tracker.keyframes.len(),
+                    ),
+                };
+                BranchInfo {
+                    id,
+                    name: branch.name.clone(),
+                    active: id == self.active,
+                    nb_frames,
+                    nb_keyframes,
+                }
+            })
+            .collect();
+        Ok(to_js_value(&infos)?)
+    }
+
+    /// Make another branch the active one, keeping the current trajectory
+    /// in the previously active branch.
+    /// Buffers of the camera path and point cloud are reallocated.
+    pub fn switch_to(
+        &mut self,
+        id: usize,
+        wasm_tracker: &mut WasmTracker,
+        camera_path: &mut CameraPath,
+        point_cloud: &mut PointCloud,
+    ) -> Result<(), JsValue> {
+        if id == self.active {
+            return Ok(());
+        }
+        let branch = get_checked_mut(&mut self.branches, id, "branches")?;
+        let snapshot = branch.snapshot.take().ok_or_else(|| {
+            TrackerError::Tracking(format!("Branch {} has no saved state", branch.name))
+        })?;
+        let current = snapshot.apply(wasm_tracker, camera_path, point_cloud);
+        self.branches[self.active].snapshot = Some(current);
+        self.active = id;
+        Ok(())
+    }
+
+    /// Export the camera trajectory of a branch in the TUM format.
+    pub fn export_tum_trajectory(
+        &self,
+        id: usize,
+        camera_path: &CameraPath,
+    ) -> Result<String, JsValue> {
+        match &get_checked(&self.branches, id, "branches")?.snapshot {
+            Some(snapshot) => Ok(tum_trajectory(
+                snapshot
+                    .camera_path
+                    .frames
+                    .iter()
+                    .map(|(timestamp, pose)| (*timestamp, pose.to_iso3())),
+            )),
+            None => Ok(camera_path.export_tum_trajectory()),
+        }
+    }
+
+    /// Export the point cloud of a branch in the PLY format, see `PointCloud::export_ply`.
+    pub fn export_ply(
+        &self,
+        id: usize,
+        point_cloud: &PointCloud,
+        binary: bool,
+        with_normals: bool,
+    ) -> Result<Vec<u8>, JsValue> {
+        match &get_checked(&self.branches, id, "branches")?.snapshot {
+            Some(snapshot) => {
+                let mut branch_cloud = PointCloud::new(0);
+                branch_cloud.restore(snapshot.point_cloud.clone());
+                Ok(branch_cloud.export_ply(binary, with_normals))
+            }
+            None => Ok(point_cloud.export_ply(binary, with_normals)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_most_probable_hypothesis() {
+        let probabilities = [0.2, 0.5, 0.3];
+        assert_eq!(select_hypothesis(&probabilities, None).unwrap(), 1);
+    }
+
+    #[test]
+    fn select_most_probable_hypothesis_with_nan() {
+        let probabilities = [f32::NAN, 0.1];
+        assert!(select_hypothesis(&probabilities, None).unwrap() < probabilities.len());
+    }
+
+    #[test]
+    fn select_forced_hypothesis() {
+        let probabilities = [0.2, 0.5, 0.3];
+        assert_eq!(select_hypothesis(&probabilities, Some(0)).unwrap(), 0);
+        assert_eq!(select_hypothesis(&probabilities, Some(2)).unwrap(), 2);
+    }
+
+    #[test]
+    fn reject_out_of_range_hypothesis() {
+        let probabilities = [0.2, 0.5, 0.3];
+        assert!(select_hypothesis(&probabilities, Some(3)).is_err());
+    }
+
+    #[test]
+    fn keyframe_pose_correction_moves_frames_tracked_from_it() {
+        // Frames 0 and 2 are keyframes, frames 1 and 3 are tracked from them.
+        let old_poses: Vec<Iso3> = (0..4)
+            .map(|i| Iso3::translation(i as f32, 0.0, 0.0))
+            .collect();
+        let mut wasm_tracker = WasmTracker::new();
+        wasm_tracker.poses_history = old_poses.clone();
+        wasm_tracker.keyframes_frame_ids = vec![0, 2];
+        wasm_tracker.tracker_keyframe_id = 2;
+        let mut camera_path = CameraPath::new(0);
+        camera_path.frames = old_poses.iter().map(|&pose| (0.0, pose)).collect();
+        camera_path.poses = vec![0.0; 3 * old_poses.len()];
+        camera_path.indices_kf = vec![0, 6];
+        camera_path.end = camera_path.poses.len();
+
+        let axis = Vector3::z() * std::f32::consts::FRAC_PI_2;
+        let new_pose = Iso3::new(Vector3::new(0.0, 1.0, 0.0), axis);
+        wasm_tracker.set_keyframe_pose(0, new_pose).unwrap();
+        camera_path.set_keyframe_pose(0, new_pose).unwrap();
+
+        // Frame 1 keeps its pose relative to keyframe 0.
+        let expected = new_pose * old_poses[1];
+        for pose in &[wasm_tracker.poses_history[1], camera_path.frames[1].1] {
+            let distance = (pose.translation.vector - expected.translation.vector).norm();
+            assert!(distance < 1e-5, "{}", distance);
+            assert!(pose.rotation.angle_to(&expected.rotation) < 1e-5);
+        }
+        let translation = expected.translation.vector;
+        for (value, expected) in camera_path.poses[3..6].iter().zip(translation.iter()) {
+            assert!((value - expected).abs() < 1e-5);
+        }
+        assert_eq!(wasm_tracker.poses_history[0], new_pose);
+        // Frames from the next keyframe do not move.
+        assert_eq!(wasm_tracker.poses_history[2..], old_poses[2..]);
+        assert_eq!(camera_path.frames[3].1, old_poses[3]);
+    }
+
+    // Synthetic sequence ######################################################
+
+    const WIDTH: usize = 320;
+    const HEIGHT: usize = 240;
+    /// Depth of the textured plane facing the camera, in meters.
+    const PLANE_DEPTH: f32 = 2.0;
+
+    fn calibration() -> Calibration {
+        Calibration {
+            fx: 260.0,
+            fy: 260.0,
+            cx: 159.5,
+            cy: 119.5,
+            depth_scale: tum_rgbd::DEPTH_SCALE,
+            width: Some(WIDTH),
+            height: Some(HEIGHT),
+        }
+    }
+
+    /// Intensity of the plane at world coordinates (x, y).
+    fn texture(x: f32, y: f32) -> u8 {
+        let value =
+            127.0 + 60.0 * (40.0 * x).sin() * (30.0 * y).cos() + 40.0 * (17.0 * x + 23.0 * y).sin();
+        value.clamp(0.0, 255.0) as u8
+    }
+
+    /// Color and depth PNG images of the plane seen by a camera translated by `tx`.
+    fn render(tx: f32) -> (Vec<u8>, Vec<u8>) {
+        let calib = calibration();
+        let mut rgb = Vec::with_capacity(3 * WIDTH * HEIGHT);
+        let mut depth = Vec::with_capacity(2 * WIDTH * HEIGHT);
+        let depth_value = (PLANE_DEPTH * calib.depth_scale) as u16;
+        for v in 0..HEIGHT {
+            for u in 0..WIDTH {
+                let x = (u as f32 - calib.cx) / calib.fx * PLANE_DEPTH + tx;
+                let y = (v as f32 - calib.cy) / calib.fy * PLANE_DEPTH;
+                let intensity = texture(x, y);
+                rgb.extend_from_slice(&[intensity, intensity, intensity]);
+                depth.extend_from_slice(&depth_value.to_be_bytes());
+            }
+        }
+        (
+            encode_png(&rgb, png::ColorType::RGB, png::BitDepth::Eight),
+            encode_png(&depth, png::ColorType::Grayscale, png::BitDepth::Sixteen),
+        )
+    }
+
+    fn encode_png(data: &[u8], color: png::ColorType, depth: png::BitDepth) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut buffer, WIDTH as u32, HEIGHT as u32);
+            encoder.set(color).set(depth);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(data).unwrap();
+        }
+        buffer
+    }
+
+    fn append_file(builder: &mut tar::Builder<Vec<u8>>, name: &str, content: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, content).unwrap();
+    }
+
+    /// Tar archive of a sequence where the camera moves sideways by `translations`.
+    fn synthetic_archive(translations: &[f32]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut rgb_list = String::new();
+        let mut depth_list = String::new();
+        for (i, &tx) in translations.iter().enumerate() {
+            let timestamp = 0.1 * i as f64;
+            let (rgb, depth) = render(tx);
+            append_file(&mut builder, &format!("rgb/{}.png", i), &rgb);
+            append_file(&mut builder, &format!("depth/{}.png", i), &depth);
+            rgb_list.push_str(&format!("{} rgb/{}.png\n", timestamp, i));
+            depth_list.push_str(&format!("{} depth/{}.png\n", timestamp, i));
+        }
+        append_file(&mut builder, "rgb.txt", rgb_list.as_bytes());
+        append_file(&mut builder, "depth.txt", depth_list.as_bytes());
+        builder.into_inner().unwrap()
+    }
+
+    /// Tracker initialized on the first frame, having tracked the second one as a keyframe.
+    fn tracked_sequence(true_tx: f32) -> WasmTracker {
+        let mut wasm_tracker = WasmTracker::new();
+        wasm_tracker.tar_buffer = synthetic_archive(&[0.0, true_tx]);
+        wasm_tracker.entries = tar_entries(&wasm_tracker.tar_buffer).unwrap();
+        let (associations, _) = wasm_tracker
+            .load_associations(associate::DEFAULT_MAX_DIFFERENCE)
+            .unwrap();
+        assert_eq!(associations.len(), 2);
+        wasm_tracker.associations = associations;
+        let params = TrackerParams {
+            nb_levels: 4,
+            ..TrackerParams::default()
+        };
+        wasm_tracker.init_with(calibration(), params).unwrap();
+        wasm_tracker.track_frame(1, true).unwrap();
+        wasm_tracker
+    }
+
+    /// Points clicked in the first keyframe, on candidates spread over the image,
+    /// and where they are seen from the second frame at its true pose.
+    fn synthetic_clicks(wasm_tracker: &WasmTracker, true_pose: &Iso3) -> P3pRequest {
+        let tracker = wasm_tracker.tracker().unwrap();
+        let intrinsics = &tracker.intrinsics()[1];
+        let (width, height) = (WIDTH as f32 / 2.0, HEIGHT as f32 / 2.0);
+        let targets = [
+            (0.25 * width, 0.25 * height),
+            (0.75 * width, 0.25 * height),
+            (0.5 * width, 0.75 * height),
+        ];
+        let mut ref_points = Vec::new();
+        let mut key_points = Vec::new();
+        for &target in targets.iter() {
+            let (_, (u, v)) = closest_to(target, &wasm_tracker.keyframes_candidates[0]).unwrap();
+            let world = intrinsics.back_project(Point2::new(u, v), PLANE_DEPTH);
+            let projected = intrinsics.project(true_pose.inverse() * world);
+            ref_points.push((u, v));
+            key_points.push((projected.x / projected.z, projected.y / projected.z));
+        }
+        P3pRequest {
+            base_frame_id: 0,
+            last_tracked_frame_id: 1,
+            ref_points,
+            key_points,
+            pnp: PnpParams::default(),
+        }
+    }
+
+    #[test]
+    fn reset_at_p3p_with_synthetic_clicks() {
+        let true_tx = 0.05;
+        let true_pose = Iso3::translation(true_tx, 0.0, 0.0);
+        let mut wasm_tracker = tracked_sequence(true_tx);
+        assert_eq!(wasm_tracker.nb_keyframes(), 2);
+        let request = synthetic_clicks(&wasm_tracker, &true_pose);
+
+        let reset = wasm_tracker.reset_p3p(request.clone(), None).unwrap();
+        assert_eq!(reset.keyframe_id, 1);
+        assert_eq!(reset.probabilities.len(), wasm_tracker.p3p_poses.len());
+        assert_eq!(reset.hypothesis, most_probable(&reset.probabilities));
+        let sum: f32 = reset.probabilities.iter().sum();
+        assert!((sum - 1.0).abs() < 1e-4);
+
+        // One of the P3P hypotheses, after the current pose, is the true pose.
+        let closest_distance = wasm_tracker.p3p_poses[1..]
+            .iter()
+            .map(|pose| (pose.translation.vector - true_pose.translation.vector).norm())
+            .fold(f32::INFINITY, f32::min);
+        assert!(closest_distance < 1e-2, "{}", closest_distance);
+        assert_eq!(
+            wasm_tracker.edits.last(),
+            Some(&Edit::ChooseP3pInitial {
+                id: reset.hypothesis,
+                base_frame_id: 0,
+                p3p: request,
+            })
+        );
+    }
+
+    #[test]
+    fn reset_at_p3p_rejects_out_of_range_hypothesis() {
+        let true_tx = 0.05;
+        let true_pose = Iso3::translation(true_tx, 0.0, 0.0);
+        let mut wasm_tracker = tracked_sequence(true_tx);
+        let request = synthetic_clicks(&wasm_tracker, &true_pose);
+        let nb_edits = wasm_tracker.edits.len();
+        let poses_history = wasm_tracker.poses_history.clone();
+
+        assert!(wasm_tracker.reset_p3p(request, Some(100)).is_err());
+        assert!(wasm_tracker.p3p_poses.is_empty());
+        assert!(wasm_tracker.p3p_request.is_none());
+        assert_eq!(wasm_tracker.edits.len(), nb_edits);
+        assert_eq!(wasm_tracker.poses_history, poses_history);
+    }
+
+    #[test]
+    fn reset_at_p3p_rejects_frame_that_is_not_a_later_keyframe() {
+        let true_tx = 0.05;
+        let true_pose = Iso3::translation(true_tx, 0.0, 0.0);
+        let mut wasm_tracker = tracked_sequence(true_tx);
+        let request = synthetic_clicks(&wasm_tracker, &true_pose);
+        let nb_edits = wasm_tracker.edits.len();
+        let poses_history = wasm_tracker.poses_history.clone();
+
+        let not_tracked = P3pRequest {
+            last_tracked_frame_id: 2,
+            ..request.clone()
+        };
+        assert!(wasm_tracker.prepare_p3p_reset(not_tracked, None).is_err());
+        let same_frame = P3pRequest {
+            base_frame_id: 1,
+            ..request
+        };
+        assert!(wasm_tracker.reset_p3p(same_frame, None).is_err());
+        assert!(wasm_tracker.p3p_request.is_none());
+        assert_eq!(wasm_tracker.edits.len(), nb_edits);
+        assert_eq!(wasm_tracker.poses_history, poses_history);
     }
 }
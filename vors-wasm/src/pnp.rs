@@ -0,0 +1,378 @@
+use nalgebra::{
+    Matrix6, Point3, Quaternion, Translation3, UnitQuaternion, Vector3, Vector4, Vector6,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::error::TrackerError;
+use visual_odometry_rs::core::camera::Intrinsics;
+use visual_odometry_rs::misc::type_aliases::{Iso3, Point2};
+
+/// Parameters of the robust PnP that can be tuned from JavaScript.
+/// Missing fields take their default value.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct PnpParams {
+    /// Maximum number of minimal sets of 3 correspondences tried by RANSAC.
+    /// All of them are tried if there are fewer.
+    pub max_iterations: usize,
+    /// Reprojection error (in pixels) under which a correspondence is an inlier.
+    pub inlier_threshold: f32,
+    /// Maximum number of Levenberg-Marquardt iterations refining the pose on inliers.
+    pub refine_iterations: usize,
+}
+
+impl Default for PnpParams {
+    fn default() -> PnpParams {
+        PnpParams {
+            max_iterations: 200,
+            inlier_threshold: 4.0,
+            refine_iterations: 20,
+        }
+    }
+}
+
+impl PnpParams {
+    /// Read parameters from a JavaScript object.
+    /// `undefined` or `null` give the default parameters.
+    pub fn from_js(value: JsValue) -> Result<PnpParams, TrackerError> {
+        if value.is_undefined() || value.is_null() {
+            return Ok(PnpParams::default());
+        }
+        let params: PnpParams = serde_wasm_bindgen::from_value(value)
+            .map_err(|e| TrackerError::Config(format!("Invalid PnP config: {}", e)))?;
+        if params.max_iterations == 0 {
+            return Err(TrackerError::Config(
+                "max_iterations must be at least 1".to_owned(),
+            ));
+        }
+        if params.inlier_threshold.is_nan() || params.inlier_threshold <= 0.0 {
+            return Err(TrackerError::Config(
+                "inlier_threshold must be strictly positive".to_owned(),
+            ));
+        }
+        Ok(params)
+    }
+}
+
+/// Camera pose estimated from 2D-3D correspondences.
+pub struct PnpSolution {
+    /// Pose of the camera in world coordinates.
+    pub pose: Iso3,
+    /// Reprojection error of each correspondence at that pose, in pixels.
+    /// Infinite for points behind the camera.
+    pub errors: Vec<f32>,
+    /// Whether each correspondence is an inlier.
+    pub inliers: Vec<bool>,
+}
+
+/// Estimate the camera pose from the world coordinates of points
+/// and their projections in the image, which may contain outliers.
+///
+/// RANSAC tries minimal sets of 3 correspondences solved with P3P,
+/// keeping the pose with the most inliers (lowest error on ties).
+/// The pose is then refined on its inliers by minimizing their reprojection error
+/// with Levenberg-Marquardt, and inliers are classified again.
+/// Minimal sets are drawn with a fixed seed, so the result is deterministic.
+pub fn solve(
+    world_points: &[Point3<f32>],
+    image_points: &[(f32, f32)],
+    intrinsics: &Intrinsics,
+    params: &PnpParams,
+) -> Result<PnpSolution, TrackerError> {
+    if world_points.len() != image_points.len() || world_points.len() < 3 {
+        return Err(TrackerError::Config(format!(
+            "PnP needs at least 3 correspondences, got {} points for {} projections",
+            world_points.len(),
+            image_points.len()
+        )));
+    }
+    let bearing_vectors: Vec<[f32; 3]> = image_points
+        .iter()
+        .map(|&(u, v)| {
+            let b = intrinsics.back_project(Point2::new(u, v), 1.0).coords;
+            ((1.0 / b[2]) * b).into()
+        })
+        .collect();
+
+    // Keep the world to camera transformation with the most inliers.
+    let mut best: Option<(usize, f32, Iso3)> = None;
+    for set in minimal_sets(world_points.len(), params.max_iterations) {
+        let world = [
+            world_points[set[0]].coords.into(),
+            world_points[set[1]].coords.into(),
+            world_points[set[2]].coords.into(),
+        ];
+        let bearings = [
+            bearing_vectors[set[0]],
+            bearing_vectors[set[1]],
+            bearing_vectors[set[2]],
+        ];
+        for p in p3p::nordberg::solve(&world, &bearings).iter() {
+            let rotation =
+                UnitQuaternion::from_quaternion(Quaternion::from(Vector4::from(p.rotation)));
+            let translation = Translation3::from(Vector3::from(p.translation));
+            let world_to_camera = Iso3::from_parts(translation, rotation);
+            let errors =
+                reprojection_errors(&world_to_camera, world_points, image_points, intrinsics);
+            let inlier_errors = errors.iter().filter(|&&e| e < params.inlier_threshold);
+            let nb_inliers = inlier_errors.clone().count();
+            let error_sum: f32 = inlier_errors.sum();
+            let is_better = match best {
+                Some((nb, sum, _)) => nb_inliers > nb || (nb_inliers == nb && error_sum < sum),
+                None => true,
+            };
+            if is_better {
+                best = Some((nb_inliers, error_sum, world_to_camera));
+            }
+        }
+    }
+    let (_, _, world_to_camera) = best.ok_or_else(|| {
+        TrackerError::Tracking("P3P found no pose for the clicked points".to_owned())
+    })?;
+
+    // Refine the pose on the inliers.
+    let errors = reprojection_errors(&world_to_camera, world_points, image_points, intrinsics);
+    let inliers: Vec<_> = world_points
+        .iter()
+        .zip(image_points.iter())
+        .zip(errors.iter())
+        .filter(|(_, &e)| e < params.inlier_threshold)
+        .map(|((&p, &uv), _)| (p, uv))
+        .collect();
+    let world_to_camera = if inliers.len() >= 3 {
+        refine(
+            world_to_camera,
+            &inliers,
+            intrinsics,
+            params.refine_iterations,
+        )
+    } else {
+        world_to_camera
+    };
+
+    let errors = reprojection_errors(&world_to_camera, world_points, image_points, intrinsics);
+    let inliers = errors
+        .iter()
+        .map(|&e| e < params.inlier_threshold)
+        .collect();
+    Ok(PnpSolution {
+        pose: world_to_camera.inverse(),
+        errors,
+        inliers,
+    })
+}
+
+/// Sets of 3 distinct correspondences tried by RANSAC:
+/// all of them if there are at most `max_sets`, otherwise `max_sets` random ones.
+fn minimal_sets(nb_points: usize, max_sets: usize) -> Vec<[usize; 3]> {
+    let n = nb_points;
+    if n * (n - 1) * (n - 2) / 6 <= max_sets {
+        let mut sets = Vec::new();
+        for i in 0..n {
+            for j in i + 1..n {
+                for k in j + 1..n {
+                    sets.push([i, j, k]);
+                }
+            }
+        }
+        return sets;
+    }
+    let mut rng = XorShift(0x9E37_79B9);
+    (0..max_sets)
+        .map(|_| {
+            let i = rng.below(n);
+            let mut j = rng.below(n - 1);
+            if j >= i {
+                j += 1;
+            }
+            let mut k = rng.below(n - 2);
+            for &taken in [i.min(j), i.max(j)].iter() {
+                if k >= taken {
+                    k += 1;
+                }
+            }
+            [i, j, k]
+        })
+        .collect()
+}
+
+/// Minimal xorshift generator, enough to draw RANSAC sets reproducibly.
+struct XorShift(u32);
+
+impl XorShift {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as usize % n
+    }
+}
+
+/// Pixel coordinates of a point in camera coordinates, if it is in front of the camera.
+fn project(point: &Point3<f32>, intrinsics: &Intrinsics) -> Option<(f32, f32)> {
+    if point.z <= 0.0 {
+        return None;
+    }
+    let (fx, fy) = intrinsics.focal;
+    let (cx, cy) = intrinsics.principal_point;
+    let x = point.x / point.z;
+    let y = point.y / point.z;
+    Some((fx * x + intrinsics.skew * y + cx, fy * y + cy))
+}
+
+fn reprojection_errors(
+    world_to_camera: &Iso3,
+    world_points: &[Point3<f32>],
+    image_points: &[(f32, f32)],
+    intrinsics: &Intrinsics,
+) -> Vec<f32> {
+    world_points
+        .iter()
+        .zip(image_points.iter())
+        .map(
+            |(p, &(u, v))| match project(&(world_to_camera * p), intrinsics) {
+                Some((pu, pv)) => (pu - u).hypot(pv - v),
+                None => f32::INFINITY,
+            },
+        )
+        .collect()
+}
+
+/// Minimize the squared reprojection errors of the correspondences
+/// with Levenberg-Marquardt, updating the pose by left multiplication
+/// with small rigid motions (translation first, then rotation).
+fn refine(
+    world_to_camera: Iso3,
+    correspondences: &[(Point3<f32>, (f32, f32))],
+    intrinsics: &Intrinsics,
+    iterations: usize,
+) -> Iso3 {
+    let (fx, fy) = intrinsics.focal;
+    let skew = intrinsics.skew;
+    let cost = |pose: &Iso3| -> f32 {
+        correspondences
+            .iter()
+            .map(|(p, (u, v))| match project(&(pose * p), intrinsics) {
+                Some((pu, pv)) => (pu - u).powi(2) + (pv - v).powi(2),
+                None => f32::INFINITY,
+            })
+            .sum()
+    };
+
+    let mut pose = world_to_camera;
+    let mut current_cost = cost(&pose);
+    let mut lambda = 1e-3;
+    for _ in 0..iterations {
+        let mut hessian = Matrix6::<f32>::zeros();
+        let mut gradient = Vector6::<f32>::zeros();
+        for &(p, (u, v)) in correspondences.iter() {
+            let pc = pose * p;
+            let (pu, pv) = match project(&pc, intrinsics) {
+                Some(uv) => uv,
+                None => continue,
+            };
+            // Derivatives of the projection with respect to the camera coordinates,
+            // then to the motion: d(pc) = d(translation) + d(rotation) x pc.
+            let z = pc.z;
+            let du = Vector3::new(fx / z, skew / z, -(fx * pc.x + skew * pc.y) / (z * z));
+            let dv = Vector3::new(0.0, fy / z, -fy * pc.y / (z * z));
+            for (d, residual) in [(du, pu - u), (dv, pv - v)].iter() {
+                let r = pc.coords.cross(d);
+                let jacobian = Vector6::new(d.x, d.y, d.z, r.x, r.y, r.z);
+                hessian += jacobian * jacobian.transpose();
+                gradient += jacobian * *residual;
+            }
+        }
+        for i in 0..6 {
+            hessian[(i, i)] *= 1.0 + lambda;
+        }
+        let step = match hessian.cholesky() {
+            Some(cholesky) => -cholesky.solve(&gradient),
+            None => break,
+        };
+        let motion = Iso3::from_parts(
+            Translation3::new(step[0], step[1], step[2]),
+            UnitQuaternion::from_scaled_axis(Vector3::new(step[3], step[4], step[5])),
+        );
+        let candidate = motion * pose;
+        let candidate_cost = cost(&candidate);
+        if candidate_cost < current_cost {
+            let converged = current_cost - candidate_cost < 1e-6 * current_cost;
+            pose = candidate;
+            current_cost = candidate_cost;
+            lambda *= 0.1;
+            if converged {
+                break;
+            }
+        } else {
+            lambda *= 10.0;
+        }
+    }
+    pose
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intrinsics() -> Intrinsics {
+        Intrinsics {
+            principal_point: (159.5, 119.5),
+            focal: (260.0, 260.0),
+            skew: 0.0,
+        }
+    }
+
+    #[test]
+    fn solve_rejects_corrupted_correspondence() {
+        let intrinsics = intrinsics();
+        let true_pose = Iso3::new(
+            Vector3::new(0.1, -0.05, 0.2),
+            Vector3::new(0.02, -0.05, 0.03),
+        );
+        let world_points: Vec<Point3<f32>> = (0..8)
+            .map(|i| {
+                let x = (i % 4) as f32 * 0.4 - 0.6;
+                let y = (i / 4) as f32 * 0.5 - 0.25;
+                Point3::new(x, y, 2.0 + 0.3 * (i % 3) as f32)
+            })
+            .collect();
+        let world_to_camera = true_pose.inverse();
+        let mut image_points: Vec<(f32, f32)> = world_points
+            .iter()
+            .map(|p| project(&(world_to_camera * p), &intrinsics).unwrap())
+            .collect();
+        let corrupted = 5;
+        image_points[corrupted].0 += 40.0;
+        image_points[corrupted].1 -= 25.0;
+
+        let params = PnpParams::default();
+        let solution = solve(&world_points, &image_points, &intrinsics, &params).unwrap();
+        let rejected: Vec<usize> = (0..world_points.len())
+            .filter(|&i| !solution.inliers[i])
+            .collect();
+        assert_eq!(rejected, vec![corrupted]);
+        let distance = (solution.pose.translation.vector - true_pose.translation.vector).norm();
+        assert!(distance < 1e-3, "{}", distance);
+        let angle = solution.pose.rotation.angle_to(&true_pose.rotation);
+        assert!(angle < 1e-3, "{}", angle);
+    }
+
+    #[test]
+    fn minimal_sets_have_distinct_indices() {
+        for n in 3..30 {
+            let sets = minimal_sets(n, 50);
+            assert!(!sets.is_empty());
+            for set in sets.iter() {
+                assert!(set.iter().all(|&i| i < n), "{:?} for {} points", set, n);
+                assert!(
+                    set[0] != set[1] && set[0] != set[2] && set[1] != set[2],
+                    "{:?} for {} points",
+                    set,
+                    n
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Calibration;
+use crate::config::TrackerParams;
+use crate::edit_log::Edit;
+use crate::error::TrackerError;
+use crate::tracking_result::Pose;
+use visual_odometry_rs::dataset::tum_rgbd::Association;
+
+/// Version of the session format, increased on incompatible changes.
+pub const SESSION_VERSION: u32 = 1;
+
+/// Magic bytes starting binary session files, followed by the version (u32 little endian).
+/// JSON session files are objects with a "version" field.
+const BINARY_MAGIC: &[u8; 8] = b"VORSSESS";
+
+/// Interactive work on a sequence, to be restored against the same archive.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    pub archive: ArchiveFingerprint,
+    pub tracker: TrackerState,
+    pub camera_path: CameraPathState,
+    pub point_cloud: PointCloudState,
+}
+
+/// Summary of the frames of an archive, to check a session matches it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchiveFingerprint {
+    pub nb_frames: usize,
+    pub first_timestamp: f64,
+    pub last_timestamp: f64,
+}
+
+impl ArchiveFingerprint {
+    /// Fingerprint of the frames associated in an archive.
+    pub fn new(associations: &[Association]) -> ArchiveFingerprint {
+        ArchiveFingerprint {
+            nb_frames: associations.len(),
+            first_timestamp: associations.first().map_or(0.0, |a| a.color_timestamp),
+            last_timestamp: associations.last().map_or(0.0, |a| a.color_timestamp),
+        }
+    }
+}
+
+/// State of the WasmTracker.
+/// The tracker itself is rebuilt from its keyframe, and keyframe images
+/// and candidates are recomputed from the archive.
+#[derive(Serialize, Deserialize)]
+pub struct TrackerState {
+    pub calibration: Calibration,
+    pub params: TrackerParams,
+    pub max_association_difference: f64,
+    pub poses_history: Vec<Pose>,
+    pub keyframes_frame_ids: Vec<usize>,
+    /// Frame the tracker currently uses as keyframe.
+    pub tracker_keyframe_id: usize,
+    pub change_keyframe: bool,
+    /// Edit log since `init`, so that it survives saving and loading sessions.
+    pub edits: Vec<Edit>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CameraPathState {
+    pub frames: Vec<(f64, Pose)>,
+    pub indices_kf: Vec<usize>,
+}
+
+/// Valid part of the PointCloud buffers.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PointCloudState {
+    pub sections: Vec<(usize, usize)>,
+    pub points: Vec<f32>,
+    pub local_points: Vec<f32>,
+    pub colors: Vec<u8>,
+    pub weights: Vec<u32>,
+    pub keyframe_poses: Vec<Pose>,
+    pub voxel_size: Option<f32>,
+    /// Occupied voxels, with the index of their point and of its section.
+    pub voxels: Vec<([i32; 3], usize, usize)>,
+    pub merges: Vec<Vec<Merge>>,
+}
+
+impl PointCloudState {
+    /// Check that buffers and sections have consistent sizes.
+    pub fn check(&self) -> Result<(), TrackerError> {
+        let nb_points = self.points.len() / 3;
+        let consistent = 3 * nb_points == self.points.len()
+            && self.local_points.len() == self.points.len()
+            && self.colors.len() == self.points.len()
+            && self.weights.len() == nb_points
+            && self.keyframe_poses.len() == self.sections.len()
+            && self.merges.len() == self.sections.len()
+            && self
+                .sections
+                .iter()
+                .all(|&(start, end)| start <= end && end <= self.points.len())
+            && self
+                .voxels
+                .iter()
+                .all(|&(_, i, section)| i < nb_points && section < self.sections.len())
+            && self
+                .merges
+                .iter()
+                .flatten()
+                .all(|m| m.point < nb_points && m.section < self.sections.len());
+        if consistent {
+            Ok(())
+        } else {
+            Err(TrackerError::Decode(
+                "Inconsistent point cloud in session".to_owned(),
+            ))
+        }
+    }
+}
+
+/// Attributes of a point before a keyframe point was merged into it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Merge {
+    pub point: usize,
+    /// Section of the point, whose keyframe pose gives its world position.
+    pub section: usize,
+    /// Position in the camera coordinates of the keyframe of the point.
+    pub local_position: [f32; 3],
+    pub color: [u8; 3],
+    pub weight: u32,
+}
+
+#[derive(Deserialize)]
+struct VersionHeader {
+    version: u32,
+}
+
+impl Session {
+    /// Check that the parts of the session are consistent with each other
+    /// and with the frames of its archive, before restoring any of them.
+    pub fn check(&self) -> Result<(), TrackerError> {
+        self.point_cloud.check()?;
+        let tracker = &self.tracker;
+        let nb_tracked = tracker.poses_history.len();
+        let tracker_consistent = nb_tracked > 0
+            && nb_tracked <= self.archive.nb_frames
+            && tracker
+                .keyframes_frame_ids
+                .iter()
+                .all(|&id| id < nb_tracked)
+            && tracker.tracker_keyframe_id < nb_tracked;
+        if !tracker_consistent {
+            return Err(TrackerError::Decode(
+                "Inconsistent tracker in session".to_owned(),
+            ));
+        }
+        let path = &self.camera_path;
+        let nb_path_frames = path.frames.len();
+        if !path
+            .indices_kf
+            .iter()
+            .all(|&i| i % 3 == 0 && i / 3 < nb_path_frames)
+        {
+            return Err(TrackerError::Decode(
+                "Inconsistent camera path in session".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Encode the session in the binary format or in JSON.
+    pub fn encode(&self, binary: bool) -> Result<Vec<u8>, TrackerError> {
+        let encode_err = |e: &dyn std::error::Error| {
+            TrackerError::Decode(format!("Could not encode session: {}", e))
+        };
+        if binary {
+            let mut bytes = BINARY_MAGIC.to_vec();
+            bytes.extend_from_slice(&self.version.to_le_bytes());
+            bytes.extend(bincode::serialize(self).map_err(|e| encode_err(&*e))?);
+            Ok(bytes)
+        } else {
+            serde_json::to_vec(self).map_err(|e| encode_err(&e))
+        }
+    }
+
+    /// Decode a session file, binary or JSON, checking its version first.
+    pub fn decode(bytes: &[u8]) -> Result<Session, TrackerError> {
+        let decode_err = |e: &dyn std::error::Error| {
+            TrackerError::Decode(format!("Invalid session file: {}", e))
+        };
+        if bytes.starts_with(BINARY_MAGIC) && bytes.len() >= BINARY_MAGIC.len() + 4 {
+            let mut version = [0; 4];
+            version.copy_from_slice(&bytes[BINARY_MAGIC.len()..BINARY_MAGIC.len() + 4]);
+            check_version(u32::from_le_bytes(version))?;
+            bincode::deserialize(&bytes[BINARY_MAGIC.len() + 4..]).map_err(|e| decode_err(&*e))
+        } else {
+            let header: VersionHeader =
+                serde_json::from_slice(bytes).map_err(|e| decode_err(&e))?;
+            check_version(header.version)?;
+            serde_json::from_slice(bytes).map_err(|e| decode_err(&e))
+        }
+    }
+}
+
+fn check_version(version: u32) -> Result<(), TrackerError> {
+    if version == SESSION_VERSION {
+        Ok(())
+    } else {
+        Err(TrackerError::Decode(format!(
+            "Session version {} is not supported (expected {})",
+            version, SESSION_VERSION
+        )))
+    }
+}
@@ -0,0 +1,118 @@
+use nalgebra::{Quaternion, Translation3, UnitQuaternion};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::error::TrackerError;
+use visual_odometry_rs::misc::type_aliases::Iso3;
+
+/// Result of tracking one frame, sent to JavaScript as a plain object.
+#[derive(Serialize)]
+pub struct TrackingResult {
+    pub frame_id: usize,
+    pub color_timestamp: f64,
+    pub depth_timestamp: f64,
+    pub pose: Pose,
+    pub keyframe_created: bool,
+    pub stats: TrackingStats,
+}
+
+/// Camera pose, as a translation and a unit quaternion in (x, y, z, w) order.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+impl From<&Iso3> for Pose {
+    fn from(iso: &Iso3) -> Pose {
+        let t = iso.translation.vector;
+        let q = iso.rotation.quaternion().coords;
+        Pose {
+            translation: [t.x, t.y, t.z],
+            rotation: [q[0], q[1], q[2], q[3]],
+        }
+    }
+}
+
+impl Pose {
+    /// Read a pose sent from JavaScript, `{ translation: [x, y, z], rotation: [x, y, z, w] }`.
+    /// The rotation quaternion does not need to be normalized.
+    pub fn from_js(value: JsValue) -> Result<Pose, TrackerError> {
+        let pose: Pose = serde_wasm_bindgen::from_value(value)
+            .map_err(|e| TrackerError::Config(format!("Invalid pose: {}", e)))?;
+        let mut values = pose.translation.iter().chain(pose.rotation.iter());
+        let norm: f32 = pose.rotation.iter().map(|x| x * x).sum();
+        if values.any(|x| !x.is_finite()) || norm == 0.0 {
+            return Err(TrackerError::Config(
+                "Pose must be finite with a non zero rotation".to_owned(),
+            ));
+        }
+        Ok(pose)
+    }
+
+    pub fn to_iso3(self) -> Iso3 {
+        let [tx, ty, tz] = self.translation;
+        let [qx, qy, qz, qw] = self.rotation;
+        Iso3::from_parts(
+            Translation3::new(tx, ty, tz),
+            UnitQuaternion::from_quaternion(Quaternion::new(qw, qx, qy, qz)),
+        )
+    }
+}
+
+/// Optimization statistics of the frame, measured against the keyframe
+/// it was tracked from.
+#[derive(Serialize)]
+pub struct TrackingStats {
+    /// Mean photometric reprojection error at the final pose.
+    pub residual: f32,
+    /// Number of iterations of each pyramid level, coarsest first.
+    /// `None` while the tracker does not report them.
+    pub iterations: Option<Vec<usize>>,
+    /// Ratio of keyframe points reprojecting inside the frame.
+    pub inlier_ratio: f32,
+}
+
+/// Description of a trajectory branch, sent to JavaScript.
+#[derive(Serialize)]
+pub struct BranchInfo {
+    pub id: usize,
+    pub name: String,
+    pub active: bool,
+    pub nb_frames: usize,
+    pub nb_keyframes: usize,
+}
+
+/// Outcome of a P3P reinitialization.
+#[derive(Serialize)]
+pub struct P3pReset {
+    /// Keyframe reinitialized with the chosen pose.
+    pub keyframe_id: usize,
+    /// Chosen hypothesis, 0 being the current pose.
+    pub hypothesis: usize,
+    /// Probability of each hypothesis.
+    pub probabilities: Vec<f32>,
+    /// Ids of the pairs of points rejected by robust PnP.
+    pub rejected: Vec<usize>,
+}
+
+/// P3P hypotheses shown before choosing one of them.
+#[derive(Serialize)]
+pub struct P3pVisualization {
+    /// Probability of each hypothesis, the current pose coming first.
+    pub probabilities: Vec<f32>,
+    /// Ids of the pairs of points rejected by robust PnP.
+    pub rejected: Vec<usize>,
+}
+
+/// Pose estimated by robust PnP from clicked points.
+#[derive(Serialize)]
+pub struct PnpResult {
+    pub pose: Pose,
+    /// Ids of the pairs of points consistent with the pose.
+    pub inliers: Vec<usize>,
+    /// Ids of the pairs of points rejected as outliers.
+    pub rejected: Vec<usize>,
+    /// Reprojection error of each pair of points, in pixels.
+    pub errors: Vec<f32>,
+}
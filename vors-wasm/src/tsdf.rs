@@ -0,0 +1,389 @@
+use nalgebra::{DMatrix, Point3, Vector3};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::JsValue;
+
+use crate::camera::Calibration;
+use crate::error::TrackerError;
+use visual_odometry_rs::misc::type_aliases::Iso3;
+
+/// Maximum weight of a voxel, so that the volume can still adapt to new observations.
+const MAX_WEIGHT: f32 = 64.0;
+
+/// Parameters of the TSDF volume that can be tuned from JavaScript.
+/// Missing fields take their default value.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct TsdfParams {
+    /// Size of a voxel, in meters.
+    pub voxel_size: f32,
+    /// Distance to the surface (in meters) beyond which the signed distance is truncated.
+    pub truncation: f32,
+    /// Depth measurements further than this (in meters) are ignored.
+    pub max_depth: f32,
+    /// Only one pixel every `pixel_step` rows and columns is used
+    /// to find the voxels observed by a depth map.
+    pub pixel_step: usize,
+}
+
+impl Default for TsdfParams {
+    fn default() -> TsdfParams {
+        TsdfParams {
+            voxel_size: 0.01,
+            truncation: 0.04,
+            max_depth: 3.0,
+            pixel_step: 2,
+        }
+    }
+}
+
+impl TsdfParams {
+    /// Read parameters from a JavaScript object.
+    /// `undefined` or `null` give the default parameters.
+    pub fn from_js(value: JsValue) -> Result<TsdfParams, TrackerError> {
+        if value.is_undefined() || value.is_null() {
+            return Ok(TsdfParams::default());
+        }
+        let params: TsdfParams = serde_wasm_bindgen::from_value(value)
+            .map_err(|e| TrackerError::Config(format!("Invalid TSDF config: {}", e)))?;
+        params.validate()?;
+        Ok(params)
+    }
+
+    fn validate(&self) -> Result<(), TrackerError> {
+        if !self.voxel_size.is_finite() || self.voxel_size <= 0.0 {
+            return Err(TrackerError::Config(
+                "voxel_size must be finite and strictly positive".to_owned(),
+            ));
+        }
+        // Cells crossed by the surface must have all their corners in the truncation band.
+        if !self.truncation.is_finite() || self.truncation < 2.0 * self.voxel_size {
+            return Err(TrackerError::Config(
+                "truncation must be finite and at least twice voxel_size".to_owned(),
+            ));
+        }
+        if self.max_depth.is_nan() || self.max_depth <= 0.0 {
+            return Err(TrackerError::Config(
+                "max_depth must be strictly positive".to_owned(),
+            ));
+        }
+        if self.pixel_step == 0 {
+            return Err(TrackerError::Config(
+                "pixel_step must be at least 1".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Voxel {
+    /// Truncated signed distance, normalized in [-1, 1], positive in free space.
+    tsdf: f32,
+    weight: f32,
+    intensity: f32,
+}
+
+/// Triangle mesh extracted from the volume.
+pub struct Mesh {
+    /// 3 floats per vertex.
+    pub vertices: Vec<f32>,
+    /// RGB color of each vertex, 3 bytes per vertex.
+    pub colors: Vec<u8>,
+    /// Vertex indices of each triangle, counter clockwise seen from free space.
+    pub faces: Vec<[u32; 3]>,
+}
+
+impl Mesh {
+    fn push_vertex(&mut self, position: &Point3<f32>, intensity: u8) -> u32 {
+        self.vertices
+            .extend_from_slice(&[position.x, position.y, position.z]);
+        self.colors
+            .extend_from_slice(&[intensity, intensity, intensity]);
+        (self.vertices.len() / 3 - 1) as u32
+    }
+}
+
+/// Sparse TSDF volume, only storing the voxels close to observed surfaces.
+pub struct TsdfVolume {
+    pub params: TsdfParams,
+    voxels: HashMap<[i32; 3], Voxel>,
+}
+
+impl TsdfVolume {
+    pub fn new(params: TsdfParams) -> TsdfVolume {
+        TsdfVolume {
+            params,
+            voxels: HashMap::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.voxels.clear();
+    }
+
+    pub fn nb_voxels(&self) -> usize {
+        self.voxels.len()
+    }
+
+    /// Fuse a depth map and its grayscale image, taken at the given camera pose.
+    pub fn integrate(
+        &mut self,
+        depth_map: &DMatrix<u16>,
+        img: &DMatrix<u8>,
+        pose: &Iso3,
+        calibration: &Calibration,
+    ) {
+        let params = &self.params;
+        let (height, width) = depth_map.shape();
+        let depth_at = |x: usize, y: usize| {
+            let depth = f32::from(depth_map[(y, x)]) / calibration.depth_scale;
+            if depth > 0.0 && depth <= params.max_depth {
+                Some(depth)
+            } else {
+                None
+            }
+        };
+
+        // Gather the voxels in the truncation band along the rays of observed pixels.
+        let mut band = HashSet::new();
+        let ray_step = 0.5 * params.voxel_size;
+        let nb_ray_samples = (2.0 * params.truncation / ray_step).ceil() as usize;
+        for y in (0..height).step_by(params.pixel_step) {
+            for x in (0..width).step_by(params.pixel_step) {
+                if let Some(depth) = depth_at(x, y) {
+                    let ray = Vector3::new(
+                        (x as f32 - calibration.cx) / calibration.fx,
+                        (y as f32 - calibration.cy) / calibration.fy,
+                        1.0,
+                    );
+                    let distance = depth * ray.norm();
+                    let direction = ray.normalize();
+                    for k in 0..=nb_ray_samples {
+                        let t = distance - params.truncation + k as f32 * ray_step;
+                        if t > 0.0 {
+                            let point = pose * Point3::from(t * direction);
+                            band.insert(voxel_index(&point, params.voxel_size));
+                        }
+                    }
+                }
+            }
+        }
+
+        // Update each voxel of the band with its projective signed distance.
+        let world_to_camera = pose.inverse();
+        for index in band {
+            let p = world_to_camera * self.voxel_center(&index);
+            if p.z <= 0.0 {
+                continue;
+            }
+            let u = (calibration.fx * p.x / p.z + calibration.cx).round();
+            let v = (calibration.fy * p.y / p.z + calibration.cy).round();
+            if u < 0.0 || v < 0.0 || u >= width as f32 || v >= height as f32 {
+                continue;
+            }
+            let (u, v) = (u as usize, v as usize);
+            let depth = match depth_at(u, v) {
+                Some(depth) => depth,
+                None => continue,
+            };
+            let sdf = depth - p.z;
+            if sdf < -params.truncation {
+                continue;
+            }
+            let tsdf = (sdf / params.truncation).min(1.0);
+            let intensity = f32::from(img[(v, u)]);
+            let voxel = self.voxels.entry(index).or_insert(Voxel {
+                tsdf: 0.0,
+                weight: 0.0,
+                intensity: 0.0,
+            });
+            let weight = voxel.weight;
+            voxel.tsdf = (weight * voxel.tsdf + tsdf) / (weight + 1.0);
+            voxel.intensity = (weight * voxel.intensity + intensity) / (weight + 1.0);
+            voxel.weight = (weight + 1.0).min(MAX_WEIGHT);
+        }
+    }
+
+    /// Extract the zero level set of the volume with marching cubes.
+    /// Cells are formed by 8 neighbour voxel centers, and only cells with all their
+    /// corners observed and inside the truncation band produce triangles.
+    pub fn extract_mesh(&self) -> Mesh {
+        let edges = cell_edges();
+        let table = contour_table(&edges);
+        let mut mesh = Mesh {
+            vertices: Vec::new(),
+            colors: Vec::new(),
+            faces: Vec::new(),
+        };
+        // Vertex of each voxel edge, keyed by (first voxel, axis).
+        let mut edge_vertices: HashMap<([i32; 3], usize), u32> = HashMap::new();
+
+        for &origin in self.voxels.keys() {
+            let mut corners = [None; 8];
+            for (c, corner) in corners.iter_mut().enumerate() {
+                *corner = self.voxels.get(&offset(&origin, &corner_offset(c)));
+            }
+            let corners: Option<Vec<&Voxel>> = corners.iter().cloned().collect();
+            let corners = match corners {
+                Some(corners) => corners,
+                None => continue,
+            };
+            if corners.iter().any(|v| v.tsdf.abs() >= 1.0) {
+                continue;
+            }
+            let case = corners
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| v.tsdf < 0.0)
+                .fold(0, |case, (c, _)| case | (1 << c));
+
+            for contour in table[case].iter() {
+                let vertices: Vec<u32> = contour
+                    .iter()
+                    .map(|&edge| {
+                        let (c, axis) = edges[edge];
+                        let start = offset(&origin, &corner_offset(c));
+                        *edge_vertices.entry((start, axis)).or_insert_with(|| {
+                            let (a, b) = (corners[c], corners[c | (1 << axis)]);
+                            let t = a.tsdf / (a.tsdf - b.tsdf);
+                            let mut position = self.voxel_center(&start);
+                            position[axis] += t * self.params.voxel_size;
+                            let intensity = a.intensity + t * (b.intensity - a.intensity);
+                            mesh.push_vertex(&position, intensity as u8)
+                        })
+                    })
+                    .collect();
+                for i in 1..vertices.len() - 1 {
+                    mesh.faces.push([vertices[0], vertices[i], vertices[i + 1]]);
+                }
+            }
+        }
+        mesh
+    }
+
+    fn voxel_center(&self, index: &[i32; 3]) -> Point3<f32> {
+        let size = self.params.voxel_size;
+        Point3::new(
+            (index[0] as f32 + 0.5) * size,
+            (index[1] as f32 + 0.5) * size,
+            (index[2] as f32 + 0.5) * size,
+        )
+    }
+}
+
+/// Index of the voxel containing a point, in a grid with the given voxel size.
+pub fn voxel_index(point: &Point3<f32>, voxel_size: f32) -> [i32; 3] {
+    [
+        (point.x / voxel_size).floor() as i32,
+        (point.y / voxel_size).floor() as i32,
+        (point.z / voxel_size).floor() as i32,
+    ]
+}
+
+fn offset(index: &[i32; 3], delta: &[i32; 3]) -> [i32; 3] {
+    [
+        index[0] + delta[0],
+        index[1] + delta[1],
+        index[2] + delta[2],
+    ]
+}
+
+/// Offset of the corner `c` of a cell, whose bits are its (x, y, z) coordinates.
+fn corner_offset(c: usize) -> [i32; 3] {
+    [(c & 1) as i32, ((c >> 1) & 1) as i32, ((c >> 2) & 1) as i32]
+}
+
+/// The 12 edges of a cell, as (corner, axis), the other corner being `corner | 1 << axis`.
+fn cell_edges() -> Vec<(usize, usize)> {
+    let mut edges = Vec::with_capacity(12);
+    for c in 0..8 {
+        for axis in 0..3 {
+            if c & (1 << axis) == 0 {
+                edges.push((c, axis));
+            }
+        }
+    }
+    edges
+}
+
+/// Closed contours of the surface on the faces of a cell, as edge indices,
+/// for each of the 256 configurations of a cell.
+/// Bit `c` of a configuration is set when corner `c` is inside (negative).
+///
+/// Instead of the classic hard-coded triangle table, contours are built face by face:
+/// walking each face counter clockwise seen from outside the cell,
+/// an edge where the walk enters the inside region is connected to the next edge
+/// where it exits. This isolates inside corners on ambiguous faces,
+/// consistently for the two cells sharing the face, so the mesh has no holes.
+///
+/// Contours are triangulated as fans from their first edge, chosen so that
+/// no diagonal joins two vertices of the same cell face, which would be shared
+/// with the neighbour cell and make the mesh non manifold.
+/// Such a fan origin exists for all configurations.
+fn contour_table(edges: &[(usize, usize)]) -> Vec<Vec<Vec<usize>>> {
+    let edge_index = |a: usize, b: usize| {
+        let (c, axis) = (a.min(b), (a ^ b).trailing_zeros() as usize);
+        edges.iter().position(|&e| e == (c, axis)).unwrap()
+    };
+    // Faces (axis, side) containing an edge.
+    let edge_faces = |edge: usize| {
+        let (c, axis) = edges[edge];
+        (0..3)
+            .filter(move |&a| a != axis)
+            .map(move |a| (a, (c >> a) & 1))
+    };
+    let share_face = |e1: usize, e2: usize| edge_faces(e1).any(|f| edge_faces(e2).any(|g| f == g));
+
+    (0..256)
+        .map(|case: usize| {
+            let inside = |c: usize| case & (1 << c) != 0;
+            let mut next = [None; 12];
+            for axis in 0..3 {
+                let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                for side in 0..2 {
+                    let base = side << axis;
+                    let mut face = [base, base | 1 << u, base | 1 << u | 1 << v, base | 1 << v];
+                    if side == 0 {
+                        face.reverse();
+                    }
+                    // (edge, entering) for each sign change along the face.
+                    let crossings: Vec<(usize, bool)> = (0..4)
+                        .map(|k| (face[k], face[(k + 1) % 4]))
+                        .filter(|&(a, b)| inside(a) != inside(b))
+                        .map(|(a, b)| (edge_index(a, b), inside(b)))
+                        .collect();
+                    for (k, &(edge, entering)) in crossings.iter().enumerate() {
+                        if entering {
+                            next[edge] = Some(crossings[(k + 1) % crossings.len()].0);
+                        }
+                    }
+                }
+            }
+
+            let mut contours = Vec::new();
+            let mut visited = [false; 12];
+            for start in 0..12 {
+                if visited[start] || next[start].is_none() {
+                    continue;
+                }
+                let mut contour = vec![start];
+                visited[start] = true;
+                let mut edge = next[start].unwrap();
+                while edge != start {
+                    contour.push(edge);
+                    visited[edge] = true;
+                    edge = next[edge].unwrap();
+                }
+                // Start from a fan origin whose diagonals all cross the cell.
+                let n = contour.len();
+                let fan_origin = (0..n)
+                    .find(|&k| (2..n - 1).all(|i| !share_face(contour[k], contour[(k + i) % n])))
+                    .unwrap_or(0);
+                contour.rotate_left(fan_origin);
+                contours.push(contour);
+            }
+            contours
+        })
+        .collect()
+}